@@ -0,0 +1,293 @@
+use super::patch::InvalidEdit;
+use crate::utils::dirs;
+use anyhow::Result;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+/// 基础配置 profile.json 在合并结果中的名字
+pub const BASE_LAYER: &str = "base";
+
+/// 叠加在 profile 之上的配置片段，文件位于 layers 目录
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ILayer {
+    /// 文件名，不带 .json 后缀
+    pub name: String,
+
+    /// 数组的合并方式
+    #[serde(default)]
+    pub arrays: ArrayStrategy,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disabled: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ArrayStrategy {
+    /// 追加到原数组后面
+    Append,
+    /// 整个替换原数组
+    Replace,
+    /// 按 tag 字段合并同名的元素，其余的追加
+    Tag,
+}
+
+impl Default for ArrayStrategy {
+    fn default() -> Self {
+        ArrayStrategy::Tag
+    }
+}
+
+/// 合并后的配置，以及每个字段来自哪一层
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct Composed {
+    pub config: Value,
+    /// json pointer -> 层的名字
+    pub sources: BTreeMap<String, String>,
+}
+
+fn layer_path(name: &str) -> Result<PathBuf> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+        && !name.starts_with('.');
+    if !valid || name == BASE_LAYER {
+        return Err(InvalidEdit(format!("invalid layer name \"{name}\"")).into());
+    }
+    Ok(dirs::layers_dir().join(format!("{name}.json")))
+}
+
+pub fn read_layer(name: &str) -> Result<Value> {
+    let path = layer_path(name)?;
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+pub fn write_layer(name: &str, value: &Value) -> Result<()> {
+    if !value.is_object() {
+        return Err(InvalidEdit(format!("layer \"{name}\" must be a json object")).into());
+    }
+    let path = layer_path(name)?;
+    fs::create_dir_all(dirs::layers_dir())?;
+    fs::write(path, serde_json::to_string_pretty(value)?.as_bytes())?;
+    Ok(())
+}
+
+pub fn remove_layer(name: &str) -> Result<()> {
+    let path = layer_path(name)?;
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// 按顺序将各层合并到 base 上
+pub fn compose(base: Value, layers: &[ILayer]) -> Result<Composed> {
+    compose_with(base, layers, read_layer)
+}
+
+fn compose_with(
+    base: Value,
+    layers: &[ILayer],
+    read_layer: impl Fn(&str) -> Result<Value>,
+) -> Result<Composed> {
+    let mut sources = BTreeMap::new();
+    record(&base, "", BASE_LAYER, &mut sources);
+
+    let mut config = base;
+    for layer in layers.iter().filter(|l| l.disabled != Some(true)) {
+        let value = read_layer(&layer.name)
+            .map_err(|err| anyhow::anyhow!("failed to read layer \"{}\": {err}", layer.name))?;
+        merge(&mut config, value, "", layer, &mut sources);
+    }
+
+    Ok(Composed { config, sources })
+}
+
+fn merge(
+    target: &mut Value,
+    overlay: Value,
+    pointer: &str,
+    layer: &ILayer,
+    sources: &mut BTreeMap<String, String>,
+) {
+    match (target, overlay) {
+        (Value::Object(target), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                let child = format!("{pointer}/{}", escape(&key));
+                if value.is_null() {
+                    target.remove(&key);
+                    forget(&child, sources);
+                    continue;
+                }
+                match target.get_mut(&key) {
+                    Some(existing) => merge(existing, value, &child, layer, sources),
+                    None => {
+                        record(&value, &child, &layer.name, sources);
+                        target.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Value::Array(target), Value::Array(overlay)) if layer.arrays != ArrayStrategy::Replace => {
+            for value in overlay {
+                let matched = match layer.arrays {
+                    ArrayStrategy::Tag => tag_of(&value)
+                        .and_then(|tag| target.iter().position(|v| tag_of(v) == Some(tag))),
+                    _ => None,
+                };
+
+                match matched {
+                    Some(index) => {
+                        let child = format!("{pointer}/{index}");
+                        merge(&mut target[index], value, &child, layer, sources);
+                    }
+                    None => {
+                        let child = format!("{pointer}/{}", target.len());
+                        record(&value, &child, &layer.name, sources);
+                        target.push(value);
+                    }
+                }
+            }
+        }
+        (target, overlay) => {
+            forget(pointer, sources);
+            record(&overlay, pointer, &layer.name, sources);
+            *target = overlay;
+        }
+    }
+}
+
+fn tag_of(value: &Value) -> Option<&str> {
+    value.get("tag").and_then(|tag| tag.as_str())
+}
+
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+/// 记录 value 中每个叶子节点的来源
+fn record(value: &Value, pointer: &str, name: &str, sources: &mut BTreeMap<String, String>) {
+    match value {
+        Value::Object(map) if !map.is_empty() => map.iter().for_each(|(key, item)| {
+            record(item, &format!("{pointer}/{}", escape(key)), name, sources)
+        }),
+        Value::Array(list) if !list.is_empty() => list
+            .iter()
+            .enumerate()
+            .for_each(|(index, item)| record(item, &format!("{pointer}/{index}"), name, sources)),
+        _ => {
+            sources.insert(pointer.into(), name.into());
+        }
+    }
+}
+
+/// 删除 pointer 及其子节点的来源记录
+fn forget(pointer: &str, sources: &mut BTreeMap<String, String>) {
+    let prefix = format!("{pointer}/");
+    sources.retain(|key, _| key != pointer && !key.starts_with(&prefix));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn layer(name: &str, arrays: ArrayStrategy) -> ILayer {
+        ILayer {
+            name: name.into(),
+            arrays,
+            disabled: None,
+        }
+    }
+
+    fn compose_json(base: Value, layers: &[ILayer], values: Value) -> Composed {
+        compose_with(base, layers, |name| {
+            values
+                .get(name)
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("no layer {name}"))
+        })
+        .unwrap()
+    }
+
+    fn base() -> Value {
+        json!({
+            "log": { "level": "info" },
+            "outbounds": [
+                { "tag": "direct", "type": "direct" },
+                { "tag": "proxy", "type": "shadowsocks", "server": "a.example" },
+            ],
+        })
+    }
+
+    #[test]
+    fn merges_arrays_by_tag() {
+        let values = json!({
+            "local": {
+                "outbounds": [
+                    { "tag": "proxy", "server": "b.example" },
+                    { "tag": "block", "type": "block" },
+                ],
+            },
+        });
+        let composed = compose_json(base(), &[layer("local", ArrayStrategy::Tag)], values);
+
+        let outbounds = composed.config["outbounds"].as_array().unwrap();
+        assert_eq!(outbounds.len(), 3);
+        assert_eq!(outbounds[1]["server"], "b.example");
+        assert_eq!(outbounds[1]["type"], "shadowsocks");
+        assert_eq!(outbounds[2]["tag"], "block");
+
+        assert_eq!(composed.sources["/outbounds/1/server"], "local");
+        assert_eq!(composed.sources["/outbounds/1/type"], BASE_LAYER);
+        assert_eq!(composed.sources["/outbounds/2/type"], "local");
+    }
+
+    #[test]
+    fn appends_and_replaces_arrays() {
+        let values = json!({ "extra": { "outbounds": [{ "tag": "proxy" }] } });
+
+        let appended = compose_json(
+            base(),
+            &[layer("extra", ArrayStrategy::Append)],
+            values.clone(),
+        );
+        assert_eq!(appended.config["outbounds"].as_array().unwrap().len(), 3);
+
+        let replaced = compose_json(base(), &[layer("extra", ArrayStrategy::Replace)], values);
+        assert_eq!(replaced.config["outbounds"], json!([{ "tag": "proxy" }]));
+        assert_eq!(replaced.sources["/outbounds/0/tag"], "extra");
+        assert!(!replaced.sources.contains_key("/outbounds/1/tag"));
+    }
+
+    #[test]
+    fn later_layers_win_and_null_removes() {
+        let values = json!({
+            "a": { "log": { "level": "debug", "output": "box.log" } },
+            "b": { "log": { "level": "warn", "output": null } },
+        });
+        let layers = [
+            layer("a", ArrayStrategy::Tag),
+            layer("b", ArrayStrategy::Tag),
+        ];
+        let composed = compose_json(base(), &layers, values);
+
+        assert_eq!(composed.config["log"], json!({ "level": "warn" }));
+        assert_eq!(composed.sources["/log/level"], "b");
+        assert!(!composed.sources.contains_key("/log/output"));
+    }
+
+    #[test]
+    fn skips_disabled_layers() {
+        let mut disabled = layer("missing", ArrayStrategy::Tag);
+        disabled.disabled = Some(true);
+
+        // 被禁用的层不会读取，即使文件不存在
+        let composed = compose_json(base(), &[disabled], json!({}));
+        assert_eq!(composed.config, base());
+        assert!(composed.sources.values().all(|name| name == BASE_LAYER));
+    }
+}
@@ -1,5 +1,15 @@
+mod layer;
+mod patch;
+mod secret;
 mod sing_box;
 mod sword;
+mod template;
+mod token;
 
+pub use layer::*;
+pub use patch::*;
+pub use secret::*;
 pub use sing_box::*;
 pub use sword::*;
+pub use template::*;
+pub use token::*;
@@ -0,0 +1,137 @@
+use anyhow::Result;
+use json_patch::{AddOperation, Patch, PatchOperation, ReplaceOperation};
+use serde_json::Value;
+use std::fmt;
+
+/// 修改无法应用到配置上，或修改后的配置不合法
+#[derive(Debug)]
+pub struct InvalidEdit(pub String);
+
+impl fmt::Display for InvalidEdit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid edit: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidEdit {}
+
+/// RFC 7396 JSON Merge Patch
+pub fn merge_patch(doc: &mut Value, patch: &Value) -> Result<()> {
+    json_patch::merge(doc, patch);
+    Ok(())
+}
+
+/// RFC 6902 JSON Patch
+pub fn json_patch(doc: &mut Value, patch: Value) -> Result<()> {
+    let patch = json_patch::from_value(patch)?;
+    json_patch::patch(doc, &patch)?;
+    Ok(())
+}
+
+/// 写入 json pointer 指向的位置，已存在则替换，否则插入
+pub fn pointer_set(doc: &mut Value, pointer: &str, value: Value) -> Result<()> {
+    let path = pointer.to_string();
+    let op = match doc.pointer(pointer) {
+        Some(_) => PatchOperation::Replace(ReplaceOperation { path, value }),
+        None => PatchOperation::Add(AddOperation { path, value }),
+    };
+    json_patch::patch(doc, &Patch(vec![op]))?;
+    Ok(())
+}
+
+/// 与 pointer_set 相同，但会创建缺少的上级对象
+///
+/// 上级为 null 时同样替换为空对象，其他类型的值保持不变并返回错误。
+pub fn pointer_insert(doc: &mut Value, pointer: &str, value: Value) -> Result<()> {
+    let mut parent = String::new();
+    let segments = pointer.split('/').skip(1).collect::<Vec<_>>();
+    for segment in segments.iter().take(segments.len().saturating_sub(1)) {
+        parent = format!("{parent}/{segment}");
+        if doc.pointer(&parent).map_or(true, Value::is_null) {
+            pointer_set(doc, &parent, Value::Object(Default::default()))?;
+        }
+    }
+    pointer_set(doc, pointer, value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn merge_patch_removes_nulls() {
+        let mut doc = json!({ "log": { "level": "info", "output": "box.log" }, "dns": {} });
+        let patch = json!({ "log": { "level": "debug", "output": null }, "dns": null });
+        merge_patch(&mut doc, &patch).unwrap();
+        assert_eq!(doc, json!({ "log": { "level": "debug" } }));
+
+        // 数组整体替换
+        let mut doc = json!({ "outbounds": [{ "tag": "a" }, { "tag": "b" }] });
+        merge_patch(&mut doc, &json!({ "outbounds": [{ "tag": "c" }] })).unwrap();
+        assert_eq!(doc, json!({ "outbounds": [{ "tag": "c" }] }));
+    }
+
+    #[test]
+    fn json_patch_tests_and_moves() {
+        let mut doc = json!({ "log": { "level": "info" }, "outbounds": [{ "tag": "a" }] });
+        let patch = json!([
+            { "op": "test", "path": "/log/level", "value": "info" },
+            { "op": "move", "from": "/log/level", "path": "/level" },
+            { "op": "add", "path": "/outbounds/-", "value": { "tag": "b" } },
+        ]);
+        json_patch(&mut doc, patch).unwrap();
+        assert_eq!(
+            doc,
+            json!({ "log": {}, "level": "info", "outbounds": [{ "tag": "a" }, { "tag": "b" }] })
+        );
+
+        // test 失败时整个 patch 都不生效
+        let before = doc.clone();
+        let patch = json!([
+            { "op": "remove", "path": "/level" },
+            { "op": "test", "path": "/log", "value": null },
+        ]);
+        assert!(json_patch(&mut doc, patch).is_err());
+        assert_eq!(doc, before);
+
+        assert!(json_patch(&mut doc, json!({ "op": "add" })).is_err());
+    }
+
+    #[test]
+    fn pointer_set_adds_or_replaces() {
+        let mut doc = json!({ "log": { "level": "info" }, "outbounds": [{ "tag": "a" }] });
+        pointer_set(&mut doc, "/log/level", json!("debug")).unwrap();
+        pointer_set(&mut doc, "/log/timestamp", json!(true)).unwrap();
+        pointer_set(&mut doc, "/outbounds/0", json!({ "tag": "b" })).unwrap();
+        assert_eq!(
+            doc,
+            json!({ "log": { "level": "debug", "timestamp": true }, "outbounds": [{ "tag": "b" }] })
+        );
+
+        // 上级不存在时失败，配置不变
+        let before = doc.clone();
+        assert!(pointer_set(&mut doc, "/dns/strategy", json!("ipv4_only")).is_err());
+        assert_eq!(doc, before);
+    }
+
+    #[test]
+    fn inserts_missing_parents() {
+        let mut doc = json!({ "log": {} });
+        let pointer = "/experimental/clash_api/default_mode";
+        pointer_insert(&mut doc, pointer, json!("global")).unwrap();
+        assert_eq!(doc.pointer(pointer), Some(&json!("global")));
+
+        let mut doc = json!({ "experimental": { "clash_api": null, "cache_file": {} } });
+        pointer_insert(&mut doc, pointer, json!("rule")).unwrap();
+        assert_eq!(
+            doc["experimental"]["clash_api"],
+            json!({ "default_mode": "rule" })
+        );
+        assert_eq!(doc["experimental"]["cache_file"], json!({}));
+
+        let mut doc = json!({ "experimental": 1 });
+        assert!(pointer_insert(&mut doc, pointer, json!("rule")).is_err());
+        assert_eq!(doc, json!({ "experimental": 1 }));
+    }
+}
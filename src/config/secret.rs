@@ -0,0 +1,404 @@
+use super::patch::InvalidEdit;
+use crate::utils::dirs;
+use anyhow::{anyhow, Result};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use hmac::Hmac;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use rand::RngCore;
+use serde_json::Value;
+use sha2::Sha256;
+use std::{collections::BTreeMap, fs, io::Write, path::Path};
+
+/// 设置后使用口令派生的密钥，否则使用随机生成的密钥文件
+pub const PASSPHRASE_ENV: &str = "SING_SWORD_PASSPHRASE";
+
+/// GET 接口中代替明文敏感字段的值，PUT 时原样传回会保留原来的值
+pub const REDACTED: &str = "<redacted>";
+
+/// 视为敏感信息的字段名
+const SECRET_KEYS: &[&str] = &[
+    "password",
+    "uuid",
+    "secret",
+    "web_secret",
+    "private_key",
+    "pre_shared_key",
+    "auth_str",
+    "token",
+];
+
+const PBKDF2_ROUNDS: u32 = 100_000;
+
+/// 读写 secrets.json 时加锁
+static STORE_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+/// 写入只有当前用户可读的文件，先修改权限再写入内容
+pub fn write_private(path: &Path, bytes: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+
+    // 已存在的文件不受 mode 影响
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(bytes)?;
+    Ok(())
+}
+
+/// 读取或生成长度为 len 的随机文件
+fn random_file(path: &Path, len: usize) -> Result<Vec<u8>> {
+    if path.exists() {
+        let bytes = fs::read(path)?;
+        if bytes.len() != len {
+            return Err(anyhow!("invalid key file {:?}", path));
+        }
+        return Ok(bytes);
+    }
+
+    let mut bytes = vec![0u8; len];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    write_private(path, &bytes)?;
+    Ok(bytes)
+}
+
+fn load_key() -> Result<Key> {
+    match std::env::var(PASSPHRASE_ENV) {
+        Ok(passphrase) if !passphrase.is_empty() => {
+            let salt = random_file(&dirs::secret_salt_path(), 16)?;
+            Ok(derive_key(&passphrase, &salt))
+        }
+        _ => Ok(*Key::from_slice(&random_file(
+            &dirs::secret_key_path(),
+            32,
+        )?)),
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Key {
+    let mut key = Key::default();
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(passphrase.as_bytes(), salt, PBKDF2_ROUNDS, &mut key);
+    key
+}
+
+/// 加密后的格式为 base64(nonce + 密文)
+fn encrypt(key: &Key, name: &str, value: &str) -> Result<String> {
+    let cipher = ChaCha20Poly1305::new(key);
+    let mut nonce = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let data = cipher
+        .encrypt(Nonce::from_slice(&nonce), value.as_bytes())
+        .map_err(|_| anyhow!("failed to encrypt secret \"{name}\""))?;
+    Ok(base64::encode([&nonce[..], &data].concat()))
+}
+
+fn decrypt(key: &Key, name: &str, data: &str) -> Result<String> {
+    let data = base64::decode(data)?;
+    if data.len() < 12 {
+        return Err(anyhow!("invalid secret \"{name}\""));
+    }
+    let plain = ChaCha20Poly1305::new(key)
+        .decrypt(Nonce::from_slice(&data[..12]), &data[12..])
+        .map_err(|_| anyhow!("failed to decrypt secret \"{name}\""))?;
+    Ok(String::from_utf8(plain)?)
+}
+
+fn read_store() -> Result<BTreeMap<String, String>> {
+    let path = dirs::secrets_path();
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+fn write_store(store: &BTreeMap<String, String>) -> Result<()> {
+    write_private(
+        &dirs::secrets_path(),
+        serde_json::to_string_pretty(store)?.as_bytes(),
+    )
+}
+
+fn check_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+    match valid {
+        true => Ok(()),
+        false => Err(InvalidEdit(format!("invalid secret name \"{name}\"")).into()),
+    }
+}
+
+/// 所有密文的名字
+pub fn list_secrets() -> Result<Vec<String>> {
+    let _lock = STORE_LOCK.lock();
+    Ok(read_store()?.into_keys().collect())
+}
+
+/// 加密并保存
+pub fn set_secret(name: &str, value: &str) -> Result<()> {
+    check_name(name)?;
+    let data = encrypt(&load_key()?, name, value)?;
+
+    let _lock = STORE_LOCK.lock();
+    let mut store = read_store()?;
+    store.insert(name.into(), data);
+    write_store(&store)
+}
+
+pub fn remove_secret(name: &str) -> Result<()> {
+    let _lock = STORE_LOCK.lock();
+    let mut store = read_store()?;
+    store.remove(name);
+    write_store(&store)
+}
+
+/// 保存的密文，不需要密钥，用于比较密文是否变化
+pub fn stored_secrets() -> Result<BTreeMap<String, String>> {
+    let _lock = STORE_LOCK.lock();
+    read_store()
+}
+
+/// 解密全部密文，只应在渲染交给核心的配置时使用
+pub fn read_secrets() -> Result<BTreeMap<String, String>> {
+    let _lock = STORE_LOCK.lock();
+    let store = read_store()?;
+    if store.is_empty() {
+        return Ok(store);
+    }
+
+    let key = load_key()?;
+    store
+        .into_iter()
+        .map(|(name, data)| {
+            let plain = decrypt(&key, &name, &data)?;
+            Ok((name, plain))
+        })
+        .collect()
+}
+
+/// 解析形如 `${secret:NAME}` 的单个引用，不是引用时原样返回
+pub fn resolve_secret(text: &str) -> Result<String> {
+    let name = match text
+        .strip_prefix("${secret:")
+        .and_then(|t| t.strip_suffix('}'))
+    {
+        Some(name) => name,
+        None => return Ok(text.into()),
+    };
+    read_secrets()?
+        .remove(name)
+        .ok_or_else(|| anyhow!("secret \"{name}\" not found"))
+}
+
+fn is_reference(text: &str) -> bool {
+    text.contains("${secret:")
+}
+
+/// 隐藏敏感字段的明文，引用密文的值可以原样展示
+pub fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, item) in map.iter_mut() {
+                match item {
+                    Value::String(text) if SECRET_KEYS.contains(&key.as_str()) => {
+                        if !text.is_empty() && !is_reference(text) {
+                            *text = REDACTED.into();
+                        }
+                    }
+                    _ => redact(item),
+                }
+            }
+        }
+        Value::Array(list) => list.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+/// 将提交的配置中的 REDACTED 还原成 current 中相同位置的值，数组元素按 tag 或 name 对应
+///
+/// current 中没有对应的值时拒绝，避免把 REDACTED 当作明文保存。
+pub fn unredact(value: &mut Value, current: &Value) -> Result<()> {
+    restore(value, Some(current), "")
+}
+
+/// 数组元素的名字，出站等使用 tag，其他使用 name
+fn identity(item: &Value) -> Option<(&'static str, &str)> {
+    ["tag", "name"]
+        .into_iter()
+        .find_map(|key| Some((key, item.get(key)?.as_str()?)))
+}
+
+fn restore(value: &mut Value, current: Option<&Value>, pointer: &str) -> Result<()> {
+    match value {
+        Value::Object(map) => {
+            for (key, item) in map.iter_mut() {
+                let old = current.and_then(|c| c.get(key.as_str()));
+                let key = key.replace('~', "~0").replace('/', "~1");
+                restore(item, old, &format!("{pointer}/{key}"))?;
+            }
+        }
+        Value::Array(list) => {
+            for (index, item) in list.iter_mut().enumerate() {
+                // 带 tag 或 name 的元素按名字对应，删除或调整顺序后不会错位
+                let old = match identity(item) {
+                    Some(id) => current
+                        .and_then(Value::as_array)
+                        .and_then(|c| c.iter().find(|old| identity(old) == Some(id))),
+                    None => current.and_then(|c| c.get(index)),
+                };
+                restore(item, old, &format!("{pointer}/{index}"))?;
+            }
+        }
+        Value::String(text) if text == REDACTED => match current {
+            Some(current) => *value = current.clone(),
+            None => {
+                let message = format!("{pointer} is {REDACTED} but has no value to keep");
+                return Err(InvalidEdit(message).into());
+            }
+        },
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::temp_dir;
+    use serde_json::json;
+
+    #[test]
+    fn encrypts_and_decrypts() {
+        let key = Key::from_slice(&[7u8; 32]);
+        let data = encrypt(key, "pw", "hunter2").unwrap();
+        assert_eq!(decrypt(key, "pw", &data).unwrap(), "hunter2");
+
+        // 每次加密使用新的 nonce
+        assert_ne!(encrypt(key, "pw", "hunter2").unwrap(), data);
+
+        let other = Key::from_slice(&[8u8; 32]);
+        assert!(decrypt(other, "pw", &data).is_err());
+        assert!(decrypt(key, "pw", &base64::encode([0u8; 8])).is_err());
+        assert!(decrypt(key, "pw", "not base64!").is_err());
+    }
+
+    #[test]
+    fn derives_key_from_passphrase() {
+        let salt = [1u8; 16];
+        let key = derive_key("passphrase", &salt);
+        assert_eq!(key, derive_key("passphrase", &salt));
+        assert_ne!(key, derive_key("passphrase", &[2u8; 16]));
+        assert_ne!(key, derive_key("other", &salt));
+    }
+
+    #[test]
+    fn creates_and_reuses_key_file() {
+        let dir = temp_dir("key");
+        let path = dir.join("secret.key");
+
+        let key = random_file(&path, 32).unwrap();
+        assert_eq!(key.len(), 32);
+        assert_eq!(random_file(&path, 32).unwrap(), key);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // 长度不对的密钥文件不能使用
+        fs::write(&path, [0u8; 16]).unwrap();
+        assert!(random_file(&path, 32).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn redacts_plain_secrets_only() {
+        let mut value = json!({
+            "web_secret": "abc",
+            "outbounds": [
+                { "tag": "a", "password": "plain" },
+                { "tag": "b", "password": "${secret:pw}" },
+                { "tag": "c", "password": "" },
+            ],
+        });
+        redact(&mut value);
+        assert_eq!(value["web_secret"], REDACTED);
+        assert_eq!(value["outbounds"][0]["password"], REDACTED);
+        assert_eq!(value["outbounds"][0]["tag"], "a");
+        assert_eq!(value["outbounds"][1]["password"], "${secret:pw}");
+        assert_eq!(value["outbounds"][2]["password"], "");
+    }
+
+    #[test]
+    fn unredacts_from_current_value() {
+        let current = json!({
+            "web_secret": "abc",
+            "outbounds": [{ "tag": "a", "password": "plain" }],
+        });
+        let mut value = current.clone();
+        redact(&mut value);
+        value["outbounds"][0]["server"] = json!("example.com");
+
+        unredact(&mut value, &current).unwrap();
+        assert_eq!(value["web_secret"], "abc");
+        assert_eq!(value["outbounds"][0]["password"], "plain");
+        assert_eq!(value["outbounds"][0]["server"], "example.com");
+    }
+
+    #[test]
+    fn unredacts_array_items_by_tag() {
+        let current = json!({
+            "outbounds": [
+                { "tag": "a", "password": "pa" },
+                { "tag": "b", "password": "pb" },
+                { "tag": "c", "password": "pc" },
+            ],
+            "users": [{ "name": "u", "password": "pu" }],
+        });
+        let mut value = json!({
+            "outbounds": [
+                { "tag": "c", "password": REDACTED },
+                { "tag": "a", "password": REDACTED },
+            ],
+            "users": [{ "name": "u", "password": REDACTED }],
+        });
+
+        unredact(&mut value, &current).unwrap();
+        assert_eq!(value["outbounds"][0]["password"], "pc");
+        assert_eq!(value["outbounds"][1]["password"], "pa");
+        assert_eq!(value["users"][0]["password"], "pu");
+
+        let mut renamed = json!({ "outbounds": [{ "tag": "d", "password": REDACTED }] });
+        let err = unredact(&mut renamed, &current).unwrap_err();
+        assert!(err.is::<InvalidEdit>());
+    }
+
+    #[test]
+    fn rejects_redacted_without_current_value() {
+        let current = json!({ "outbounds": [{ "tag": "a", "password": "plain" }] });
+        let mut value = json!({
+            "outbounds": [
+                { "tag": "a", "password": REDACTED },
+                { "tag": "b", "password": REDACTED },
+            ],
+        });
+        let err = unredact(&mut value, &current).unwrap_err();
+        assert!(err.is::<InvalidEdit>());
+        assert!(err.to_string().contains("/outbounds/1/password"));
+    }
+}
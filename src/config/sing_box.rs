@@ -1,9 +1,10 @@
 use anyhow::Result;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Map, Value};
 use std::path::PathBuf;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ISingBox {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub log: Option<ILog>,
@@ -20,8 +21,13 @@ pub struct ISingBox {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub inbounds: Option<Value>,
 
+    /// profile 中的字段可以是模板，渲染后才能按 IOutbound 解析
     #[serde(skip_serializing_if = "Option::is_none")]
     pub outbounds: Option<Value>,
+
+    /// sword 未建模的字段，原样保留
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 impl Default for ISingBox {
@@ -33,6 +39,7 @@ impl Default for ISingBox {
             experimental: Some(IExperimental::default()),
             inbounds: None,
             outbounds: None,
+            extra: Map::new(),
         }
     }
 }
@@ -45,7 +52,23 @@ impl ISingBox {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// 渲染后配置中的出站
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct IOutbound {
+    #[serde(rename = "type")]
+    pub outbound_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_port: Option<u16>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ILog {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub disabled: Option<bool>,
@@ -55,6 +78,9 @@ pub struct ILog {
     pub output: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub timestamp: Option<bool>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 impl Default for ILog {
@@ -64,11 +90,12 @@ impl Default for ILog {
             level: Some("info".into()),
             output: Some("box.log".into()),
             timestamp: Some(true),
+            extra: Map::new(),
         }
     }
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct IDns {
     #[serde(rename = "final")]
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -83,9 +110,12 @@ pub struct IDns {
     pub rules: Option<Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub servers: Option<Value>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct IRoute {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub geoip: Option<IGeoSiteIP>,
@@ -104,9 +134,12 @@ pub struct IRoute {
     pub default_mark: Option<u16>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub rules: Option<Value>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct IGeoSiteIP {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub path: Option<String>,
@@ -114,14 +147,20 @@ pub struct IGeoSiteIP {
     pub download_url: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub download_detour: Option<String>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct IExperimental {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub clash_api: Option<IClashAPI>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub v2ray_api: Option<IV2rayAPI>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 impl Default for IExperimental {
@@ -129,11 +168,12 @@ impl Default for IExperimental {
         IExperimental {
             clash_api: Some(IClashAPI::default()),
             v2ray_api: None,
+            extra: Map::new(),
         }
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct IClashAPI {
     #[serde(default = "default_external_controller")]
     pub external_controller: String,
@@ -149,6 +189,9 @@ pub struct IClashAPI {
     pub store_selected: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cache_file: Option<String>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 fn default_external_controller() -> String {
@@ -165,14 +208,100 @@ impl Default for IClashAPI {
             default_mode: None,
             store_selected: None,
             cache_file: None,
+            extra: Map::new(),
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct IV2rayAPI {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub listen: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stats: Option<Value>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn round_trip(config: Value) {
+        let parsed: ISingBox = serde_json::from_value(config.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), config);
+    }
+
+    #[test]
+    fn keeps_unknown_fields() {
+        round_trip(json!({
+            "log": { "level": "warn", "timestamp": true },
+            "dns": {
+                "servers": [
+                    { "tag": "google", "address": "tls://8.8.8.8" },
+                    { "tag": "local", "address": "223.5.5.5", "detour": "direct" },
+                    { "tag": "remote", "address": "fakeip" }
+                ],
+                "rules": [{ "geosite": "cn", "server": "local" }],
+                "final": "google",
+                "strategy": "ipv4_only",
+                "fakeip": { "enabled": true, "inet4_range": "198.18.0.0/15" },
+                "independent_cache": true
+            },
+            "ntp": { "enabled": true, "server": "time.apple.com", "interval": "30m" },
+            "endpoints": [{ "type": "wireguard", "tag": "wg-ep", "address": ["10.0.0.2/32"] }],
+            "inbounds": [{ "type": "mixed", "tag": "mixed-in", "listen_port": 7890 }],
+            "outbounds": [
+                { "type": "selector", "tag": "proxy", "outbounds": ["direct"] },
+                { "type": "direct", "tag": "direct" }
+            ],
+            "route": {
+                "geoip": { "download_detour": "proxy", "path": "geoip.db" },
+                "rules": [{ "rule_set": "geosite-cn", "outbound": "direct" }],
+                "rule_set": [{
+                    "tag": "geosite-cn",
+                    "type": "remote",
+                    "format": "binary",
+                    "url": "https://example.com/geosite-cn.srs"
+                }],
+                "final": "proxy",
+                "auto_detect_interface": true,
+                "find_process": true
+            },
+            "experimental": {
+                "cache_file": { "enabled": true, "path": "cache.db" },
+                "clash_api": {
+                    "external_controller": "127.0.0.1:9090",
+                    "secret": "sword",
+                    "default_mode": "rule",
+                    "access_control_allow_origin": ["http://127.0.0.1"]
+                },
+                "v2ray_api": {
+                    "listen": "127.0.0.1:8080",
+                    "stats": { "enabled": true, "outbounds": ["proxy"] },
+                    "unknown": 1
+                }
+            }
+        }));
+    }
+
+    #[test]
+    fn keeps_templated_outbounds() {
+        round_trip(json!({
+            "outbounds": [{
+                "type": "shadowsocks",
+                "tag": "${NAME}",
+                "server": "${SERVER}",
+                "server_port": "${PORT}",
+                "password": "${secret:ss}"
+            }]
+        }));
+    }
+
+    #[test]
+    fn keeps_minimal_config() {
+        round_trip(json!({ "outbounds": [{ "type": "direct" }] }));
+    }
 }
@@ -1,20 +1,51 @@
-use super::sing_box::ISingBox;
-use crate::utils::dirs;
+use super::{
+    layer::{self, Composed, ILayer},
+    patch::{pointer_insert, InvalidEdit},
+    secret,
+    sing_box::ISingBox,
+    template::{self, Variables},
+    token::{constant_time_eq, generate_token, hash_token, Access, IWebToken, TokenScope},
+};
+use crate::utils::{
+    dirs,
+    events::{self, SwordEvent},
+};
 use anyhow::Result;
 use once_cell::sync::OnceCell;
-use parking_lot::RwLock;
-use serde::{Deserialize, Serialize};
-use std::{fs, sync::Arc};
+use parking_lot::{Mutex, RwLock};
+use schemars::JsonSchema;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, fmt, fs, sync::Arc};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ISword {
     pub web_port: u16,
     pub web_allow_lan: bool,
+    pub web_listen: Option<Vec<String>>, // 监听地址，未设置时按 web_allow_lan 选择
+    pub web_unix_socket: Option<String>,
+    pub web_unix_socket_mode: Option<String>, // 八进制的文件权限，默认 600
     pub web_secret: Option<String>,
-    pub web_ui: Option<String>, // 外部的ui
+    pub web_tokens: Option<Vec<IWebToken>>, // 带权限的 api token，只保存哈希
+    pub web_ui: Option<String>,             // 外部的ui，可以是地址或已安装的 ui 的名字
+    pub web_tls_cert: Option<String>,       // 证书路径，未设置时使用自签名证书
+    pub web_tls_key: Option<String>,
+    pub web_lan_insecure: Option<bool>, // 允许局域网使用明文 http
+    pub web_cors_origins: Option<Vec<String>>, // 允许跨域访问的来源，web_ui 和 clash_ui 的地址默认允许
+    pub web_allowed_hosts: Option<Vec<String>>, // 允许的域名，ip 和 localhost 默认允许
+    pub web_rate_limit: Option<u32>,           // 每个客户端每分钟允许的请求数，0 为不限制
 
-    pub clash_ui: Option<String>, // clash 的默认外部ui
+    pub clash_ui: Option<String>, // clash 的默认外部ui，可以是地址或已安装的 ui 的名字，后者会复制到 external_ui 目录
     pub core_name: Option<String>,
+
+    pub layers: Option<Vec<ILayer>>, // 按顺序叠加在 profile 上的配置片段
+
+    pub latency_test_url: Option<String>,   // 延迟测试的地址
+    pub latency_timeout: Option<u64>,       // 延迟测试的超时，毫秒
+    pub latency_concurrency: Option<usize>, // 同时测试的节点数
+
+    pub usage_quota: Option<HashMap<String, u64>>, // 出站每月的流量上限，字节
 }
 
 impl Default for ISword {
@@ -22,10 +53,25 @@ impl Default for ISword {
         ISword {
             web_port: 33211,
             web_allow_lan: false,
+            web_listen: None,
+            web_unix_socket: None,
+            web_unix_socket_mode: None,
             web_secret: None,
+            web_tokens: None,
             web_ui: None,
+            web_tls_cert: None,
+            web_tls_key: None,
+            web_lan_insecure: None,
+            web_cors_origins: None,
+            web_allowed_hosts: None,
+            web_rate_limit: None,
             clash_ui: Some("https://yacd.haishan.me/".into()),
             core_name: None,
+            layers: None,
+            latency_test_url: None,
+            latency_timeout: None,
+            latency_concurrency: None,
+            usage_quota: None,
         }
     }
 }
@@ -35,6 +81,12 @@ pub struct Sword {
     pub config: Arc<RwLock<ISword>>,
 
     pub sing_box: Arc<RwLock<ISingBox>>,
+
+    /// 解析后的 web_secret，解析口令派生的密钥开销很大，不能每个请求都做
+    web_secret: Arc<Mutex<Option<Option<String>>>>,
+
+    /// 串行化接口和托盘对配置的修改，审计日志据此得到每次修改准确的变化
+    pub writes: Arc<Mutex<()>>,
 }
 
 impl Sword {
@@ -44,6 +96,8 @@ impl Sword {
         SWORD.get_or_init(|| Sword {
             config: Arc::new(RwLock::new(ISword::default())),
             sing_box: Arc::new(RwLock::new(ISingBox::default())),
+            web_secret: Arc::new(Mutex::new(None)),
+            writes: Arc::new(Mutex::new(())),
         })
     }
 
@@ -60,15 +114,23 @@ impl Sword {
             let mut config = self.config.write();
             *config = serde_json::from_str(fs::read_to_string(&path)?.as_str())?;
         }
+        self.clear_web_secret();
 
         Ok(())
     }
 
     pub fn init_sing_box(&self) -> Result<()> {
-        let path = dirs::sing_box_path();
+        let path = dirs::profile_path();
 
         if !path.exists() {
-            fs::create_dir_all(dirs::sing_box_dir())?;
+            fs::create_dir_all(dirs::config_dir())?;
+
+            // 旧版本直接编辑 sing/config.json，将其作为 profile 迁移过来
+            let legacy_path = dirs::sing_box_path();
+            if legacy_path.exists() {
+                let mut sb = self.sing_box.write();
+                *sb = serde_json::from_str(fs::read_to_string(&legacy_path)?.as_str())?;
+            }
 
             let sb = self.sing_box.read();
             let sb_str = serde_json::to_string_pretty(&*sb)?;
@@ -81,18 +143,56 @@ impl Sword {
         Ok(())
     }
 
-    pub fn set_config(&self, value: ISword) -> Result<()> {
+    /// 写入 sword 配置，if_match 与当前 etag 不一致时拒绝，返回新的 etag
+    pub fn set_config(&self, value: ISword, if_match: Option<&str>) -> Result<String> {
         let mut config = self.config.write();
-        *config = value;
+        check_if_match(&*config, if_match)?;
+        *config = restore_redacted(value, &*config)?;
+        let tag = etag(&*config)?;
         drop(config);
-        self.save_config()
+        self.clear_web_secret();
+        self.save_config()?;
+        events::publish(SwordEvent::ConfigChanged { etag: tag.clone() });
+        Ok(tag)
     }
 
-    pub fn set_sing_box(&self, value: ISingBox) -> Result<()> {
+    /// 写入 sing-box 配置，if_match 与当前 etag 不一致时拒绝，返回新的 etag
+    pub fn set_sing_box(&self, value: ISingBox, if_match: Option<&str>) -> Result<String> {
         let mut sb = self.sing_box.write();
-        *sb = value;
+        check_if_match(&*sb, if_match)?;
+        *sb = restore_redacted(value, &*sb)?;
+        let tag = etag(&*sb)?;
         drop(sb);
-        self.save_sing_box()
+        self.save_sing_box()?;
+        events::publish(SwordEvent::SingBoxChanged { etag: tag.clone() });
+        Ok(tag)
+    }
+
+    /// 以 json 的形式编辑 sing-box 配置，编辑结果和完整写入一样需要通过校验
+    pub fn edit_sing_box<F>(&self, if_match: Option<&str>, edit: F) -> Result<String>
+    where
+        F: FnOnce(&mut Value) -> Result<()>,
+    {
+        let sb = self.sing_box.read();
+        check_if_match(&*sb, if_match)?;
+        let current = etag(&*sb)?;
+        let mut value = serde_json::to_value(&*sb)?;
+        drop(sb);
+
+        edit(&mut value).map_err(|err| InvalidEdit(err.to_string()))?;
+        let value: ISingBox =
+            serde_json::from_value(value).map_err(|err| InvalidEdit(err.to_string()))?;
+
+        // 编辑期间配置被其他人修改过，同样视为冲突
+        self.set_sing_box(value, Some(&current))
+    }
+
+    /// 写入 clash api 的默认路由模式，clash_api 来自配置片段时 profile 中可能没有上级对象
+    pub fn set_default_mode(&self, mode: &str) -> Result<String> {
+        self.edit_sing_box(None, |value| {
+            let mode = Value::String(mode.to_lowercase());
+            pointer_insert(value, "/experimental/clash_api/default_mode", mode)
+        })
     }
 
     /// 保存到文件 sword.json
@@ -104,26 +204,194 @@ impl Sword {
         Ok(())
     }
 
-    /// 保存到文件 sing/config.json
+    /// 保存到文件 profile.json
     pub fn save_sing_box(&self) -> Result<()> {
-        let path = dirs::sing_box_path();
+        let path = dirs::profile_path();
         let sb = self.sing_box.read();
         let sb_str = serde_json::to_string_pretty(&*sb)?;
         fs::write(path, sb_str.as_bytes())?;
         Ok(())
     }
 
+    /// 将各层配置片段合并到 profile 上
+    pub fn compose_sing_box(&self) -> Result<Composed> {
+        let base = serde_json::to_value(&*self.sing_box.read())?;
+        let layers = self.config.read().layers.clone().unwrap_or_default();
+        layer::compose(base, &layers)
+    }
+
+    /// 合并配置片段并填充模板变量，得到交给核心运行的配置
+    ///
+    /// resolve_secrets 为 false 时保留密文引用，用于预览
+    pub fn render_sing_box(&self, resolve_secrets: bool) -> Result<ISingBox> {
+        let mut value = self.compose_sing_box()?.config;
+        let vars = template::read_variables()?;
+        let secrets = match resolve_secrets {
+            true => Some(secret::read_secrets()?),
+            false => None,
+        };
+        let vars = Variables {
+            vars: &vars,
+            secrets: secrets.as_ref(),
+        };
+        template::render_template(&mut value, &vars)?;
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// 写入配置片段，不在列表中的追加到最后
+    pub fn put_layer(&self, name: &str, value: &Value) -> Result<()> {
+        layer::write_layer(name, value)?;
+
+        let mut config = self.config.write();
+        let layers = config.layers.get_or_insert_with(Vec::new);
+        if layers.iter().all(|l| l.name != name) {
+            layers.push(ILayer {
+                name: name.into(),
+                arrays: Default::default(),
+                disabled: None,
+            });
+            drop(config);
+            self.save_config()?;
+        }
+        events::publish(SwordEvent::LayerChanged { name: name.into() });
+        Ok(())
+    }
+
+    /// 删除配置片段，并从列表中移除
+    pub fn delete_layer(&self, name: &str) -> Result<()> {
+        layer::remove_layer(name)?;
+
+        let mut config = self.config.write();
+        if let Some(layers) = config.layers.as_mut() {
+            layers.retain(|l| l.name != name);
+        }
+        drop(config);
+        self.save_config()?;
+        events::publish(SwordEvent::LayerChanged { name: name.into() });
+        Ok(())
+    }
+
+    /// 渲染并保存到文件 sing/config.json，其中包含解密后的密文，只有当前用户可读
+    pub fn save_runtime_sing_box(&self) -> Result<()> {
+        let sb = self.render_sing_box(true)?;
+        let sb_str = serde_json::to_string_pretty(&sb)?;
+        secret::write_private(&dirs::sing_box_path(), sb_str.as_bytes())
+    }
+
     pub fn web_info(&self) -> (u16, bool, Option<String>, Option<String>) {
         let config = self.config.read();
 
         let port = config.web_port.clone();
         let allow_lan = config.web_allow_lan.clone();
-        let secret = config.web_secret.clone();
+        let mut cache = self.web_secret.lock();
+        let secret = match &*cache {
+            Some(secret) => secret.clone(),
+            None => {
+                let resolved = config.web_secret.as_deref().map(secret::resolve_secret);
+                match resolved.transpose() {
+                    Ok(secret) => {
+                        *cache = Some(secret.clone());
+                        secret
+                    }
+                    // 密文解析失败时使用空字符串，不允许任何请求通过，也不缓存
+                    Err(err) => {
+                        log::error!(target: "app", "failed to resolve web_secret: {err}");
+                        Some(String::new())
+                    }
+                }
+            }
+        };
         let ui = config.web_ui.clone();
 
         (port, allow_lan, secret, ui)
     }
 
+    /// web_secret 或者密文变化后需要重新解析
+    fn clear_web_secret(&self) {
+        *self.web_secret.lock() = None;
+    }
+
+    /// 加密保存密文
+    pub fn set_secret(&self, name: &str, value: &str) -> Result<()> {
+        secret::set_secret(name, value)?;
+        self.clear_web_secret();
+        Ok(())
+    }
+
+    pub fn remove_secret(&self, name: &str) -> Result<()> {
+        secret::remove_secret(name)?;
+        self.clear_web_secret();
+        Ok(())
+    }
+
+    /// 校验请求携带的 token，未设置 web_secret 和 token 时不需要鉴权
+    pub fn access(&self, token: Option<&str>) -> Option<Access> {
+        let (_, _, secret, _) = self.web_info();
+        let config = self.config.read();
+        let tokens = config.web_tokens.as_deref().unwrap_or_default();
+        if secret.is_none() && tokens.is_empty() {
+            return Some(Access::Master);
+        }
+
+        let hash = hash_token(token?);
+        // web_secret 解析失败时为空字符串，不能用来匹配
+        if let Some(secret) = secret.filter(|s| !s.is_empty()) {
+            if constant_time_eq(hash.as_bytes(), hash_token(&secret).as_bytes()) {
+                return Some(Access::Master);
+            }
+        }
+        tokens
+            .iter()
+            .find(|t| constant_time_eq(hash.as_bytes(), t.hash.as_bytes()))
+            .filter(|t| !t.is_expired())
+            .map(|t| Access::Token(t.clone()))
+    }
+
+    /// 创建 token，返回只出现这一次的原文
+    pub fn mint_token(
+        &self,
+        name: &str,
+        scopes: Vec<TokenScope>,
+        expires_in: Option<u64>,
+    ) -> Result<String> {
+        if name.is_empty() {
+            return Err(InvalidEdit("token name is empty".into()).into());
+        }
+
+        let token = generate_token();
+        let now = chrono::Local::now().timestamp();
+        let mut config = self.config.write();
+        let tokens = config.web_tokens.get_or_insert_with(Vec::new);
+        if tokens.iter().any(|t| t.name == name) {
+            return Err(InvalidEdit(format!("token `{name}` already exists")).into());
+        }
+        tokens.push(IWebToken {
+            name: name.into(),
+            hash: hash_token(&token),
+            scopes,
+            created_at: now,
+            expires_at: expires_in.map(|secs| now + secs as i64),
+        });
+        drop(config);
+
+        self.save_config()?;
+        Ok(token)
+    }
+
+    pub fn revoke_token(&self, name: &str) -> Result<()> {
+        let mut config = self.config.write();
+        let tokens = config.web_tokens.get_or_insert_with(Vec::new);
+        let len = tokens.len();
+        tokens.retain(|t| t.name != name);
+        if tokens.len() == len {
+            let message = format!("token `{name}` not found");
+            return Err(std::io::Error::new(std::io::ErrorKind::NotFound, message).into());
+        }
+        drop(config);
+
+        self.save_config()
+    }
+
     pub fn core_name(&self) -> Option<String> {
         let config = self.config.read();
 
@@ -141,3 +409,73 @@ impl Sword {
         }
     }
 }
+
+/// If-Match 与当前配置的 etag 不一致
+#[derive(Debug)]
+pub struct PreconditionFailed;
+
+impl fmt::Display for PreconditionFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the config has been modified by someone else")
+    }
+}
+
+impl std::error::Error for PreconditionFailed {}
+
+/// 根据配置内容计算 etag
+pub fn etag<T: Serialize>(value: &T) -> Result<String> {
+    let bytes = serde_json::to_vec(value)?;
+    let hash = Sha256::digest(&bytes);
+    let hex: String = hash[..16].iter().map(|b| format!("{b:02x}")).collect();
+    Ok(format!("\"{hex}\""))
+}
+
+/// 提交的配置中被隐藏的敏感字段，使用当前配置中的值
+fn restore_redacted<T: Serialize + DeserializeOwned>(value: T, current: &T) -> Result<T> {
+    let mut value = serde_json::to_value(value)?;
+    secret::unredact(&mut value, &serde_json::to_value(current)?)?;
+    Ok(serde_json::from_value(value)?)
+}
+
+/// 比较 If-Match 头，支持 `*` 和多个值
+///
+/// If-Match 要求强比较，弱 etag（`W/"..."`）永远不匹配。
+fn check_if_match<T: Serialize>(value: &T, if_match: Option<&str>) -> Result<()> {
+    let if_match = match if_match {
+        Some(if_match) => if_match,
+        None => return Ok(()),
+    };
+
+    let current = etag(value)?;
+    let matched = if_match
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag == current);
+
+    match matched {
+        true => Ok(()),
+        false => Err(PreconditionFailed.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn if_match_uses_strong_comparison() {
+        let value = json!({ "log": { "level": "info" } });
+        let current = etag(&value).unwrap();
+
+        assert!(check_if_match(&value, None).is_ok());
+        assert!(check_if_match(&value, Some("*")).is_ok());
+        assert!(check_if_match(&value, Some(&current)).is_ok());
+        assert!(check_if_match(&value, Some(&format!("\"other\", {current}"))).is_ok());
+
+        let weak = format!("W/{current}");
+        let err = check_if_match(&value, Some(&weak)).unwrap_err();
+        assert!(err.is::<PreconditionFailed>());
+        assert!(check_if_match(&value, Some("\"other\"")).is_err());
+    }
+}
@@ -0,0 +1,285 @@
+use crate::utils::dirs;
+use anyhow::Result;
+use serde_json::{Map, Value};
+use std::{collections::BTreeMap, fmt, fs};
+
+/// 模板中使用了未定义的变量
+#[derive(Debug)]
+pub struct MissingVariables(pub Vec<MissingVariable>);
+
+#[derive(Debug, Clone)]
+pub struct MissingVariable {
+    pub name: String,
+    /// 占位符所在位置的 json pointer
+    pub pointer: String,
+}
+
+impl fmt::Display for MissingVariables {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = self
+            .0
+            .iter()
+            .map(|m| format!("{} (at {})", m.name, m.pointer))
+            .collect::<Vec<_>>()
+            .join(", ");
+        write!(f, "missing template variables: {list}")
+    }
+}
+
+impl std::error::Error for MissingVariables {}
+
+/// 读取本机的变量文件 variables.json
+pub fn read_variables() -> Result<Map<String, Value>> {
+    let path = dirs::variables_path();
+    if !path.exists() {
+        return Ok(Map::new());
+    }
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+/// 模板渲染时可用的变量
+pub struct Variables<'a> {
+    pub vars: &'a Map<String, Value>,
+    /// `${secret:NAME}` 引用的密文，为 None 时保留引用不做替换
+    pub secrets: Option<&'a BTreeMap<String, String>>,
+}
+
+enum Lookup {
+    Found(Value),
+    Missing,
+    Keep,
+}
+
+/// 替换配置中所有字符串里的 `${VAR}` 和 `${secret:NAME}` 占位符
+///
+/// 变量优先从 vars 中读取，其次是环境变量，`$${` 表示字面量 `${`。
+/// 整个字符串只有一个占位符时，替换为变量原本的 json 类型，便于填写端口等数字。
+pub fn render_template(value: &mut Value, vars: &Variables) -> Result<()> {
+    let mut missing = vec![];
+    render_value(value, "", vars, &mut missing);
+
+    if !missing.is_empty() {
+        return Err(MissingVariables(missing).into());
+    }
+    Ok(())
+}
+
+fn render_value(
+    value: &mut Value,
+    pointer: &str,
+    vars: &Variables,
+    missing: &mut Vec<MissingVariable>,
+) {
+    match value {
+        Value::String(text) => {
+            if let Some(name) = whole_placeholder(text).map(String::from) {
+                match lookup(&name, vars) {
+                    Lookup::Found(var) => *value = var,
+                    Lookup::Missing => missing.push(MissingVariable {
+                        name,
+                        pointer: pointer.into(),
+                    }),
+                    Lookup::Keep => {}
+                }
+                return;
+            }
+
+            let rendered = substitute(text, vars, &mut |name| {
+                missing.push(MissingVariable {
+                    name: name.into(),
+                    pointer: pointer.into(),
+                })
+            });
+            *text = rendered;
+        }
+        Value::Array(list) => {
+            for (index, item) in list.iter_mut().enumerate() {
+                render_value(item, &format!("{pointer}/{index}"), vars, missing);
+            }
+        }
+        Value::Object(map) => {
+            for (key, item) in map.iter_mut() {
+                let key = key.replace('~', "~0").replace('/', "~1");
+                render_value(item, &format!("{pointer}/{key}"), vars, missing);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn lookup(name: &str, vars: &Variables) -> Lookup {
+    if let Some(secret) = name.strip_prefix("secret:") {
+        return match vars.secrets {
+            Some(secrets) => match secrets.get(secret) {
+                Some(value) => Lookup::Found(Value::String(value.clone())),
+                None => Lookup::Missing,
+            },
+            None => Lookup::Keep,
+        };
+    }
+
+    let var = vars
+        .vars
+        .get(name)
+        .cloned()
+        .or_else(|| std::env::var(name).ok().map(Value::String));
+    match var {
+        Some(var) => Lookup::Found(var),
+        None => Lookup::Missing,
+    }
+}
+
+fn is_name(name: &str) -> bool {
+    let name = name.strip_prefix("secret:").unwrap_or(name);
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+/// 字符串是否恰好为一个 `${VAR}`
+fn whole_placeholder(text: &str) -> Option<&str> {
+    let name = text.strip_prefix("${")?.strip_suffix('}')?;
+    is_name(name).then(|| name)
+}
+
+fn substitute(text: &str, vars: &Variables, miss: &mut impl FnMut(&str)) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('$') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(after) = rest.strip_prefix("$${") {
+            output.push_str("${");
+            rest = after;
+            continue;
+        }
+
+        let placeholder = rest
+            .strip_prefix("${")
+            .and_then(|after| after.find('}').map(|end| &after[..end]))
+            .filter(|name| is_name(name));
+
+        match placeholder {
+            Some(name) => {
+                match lookup(name, vars) {
+                    Lookup::Found(Value::String(var)) => output.push_str(&var),
+                    Lookup::Found(var) => output.push_str(&var.to_string()),
+                    Lookup::Missing => miss(name),
+                    Lookup::Keep => output.push_str(&rest[..name.len() + 3]),
+                }
+                rest = &rest[name.len() + 3..];
+            }
+            None => {
+                output.push('$');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn render(
+        mut value: Value,
+        vars: Value,
+        secrets: Option<&BTreeMap<String, String>>,
+    ) -> Result<Value> {
+        let vars = match vars {
+            Value::Object(map) => map,
+            _ => Map::new(),
+        };
+        render_template(
+            &mut value,
+            &Variables {
+                vars: &vars,
+                secrets,
+            },
+        )?;
+        Ok(value)
+    }
+
+    #[test]
+    fn whole_placeholder_keeps_type() {
+        let vars = json!({ "PORT": 443, "TLS": true, "DNS": ["1.1.1.1"] });
+        let value = json!({ "port": "${PORT}", "tls": "${TLS}", "dns": "${DNS}" });
+        let value = render(value, vars, None).unwrap();
+        assert_eq!(
+            value,
+            json!({ "port": 443, "tls": true, "dns": ["1.1.1.1"] })
+        );
+    }
+
+    #[test]
+    fn substitutes_inside_strings() {
+        let vars = json!({ "HOST": "example.com", "PORT": 443 });
+        let value = json!({ "url": "https://${HOST}:${PORT}/path", "plain": "no $ here $" });
+        let value = render(value, vars, None).unwrap();
+        assert_eq!(value["url"], "https://example.com:443/path");
+        assert_eq!(value["plain"], "no $ here $");
+    }
+
+    #[test]
+    fn escapes_placeholders() {
+        let vars = json!({ "HOST": "example.com" });
+        let value = json!(["$${HOST}", "a $${HOST} ${HOST}", "${not a name}", "${HOST"]);
+        let value = render(value, vars, None).unwrap();
+        assert_eq!(
+            value,
+            json!([
+                "${HOST}",
+                "a ${HOST} example.com",
+                "${not a name}",
+                "${HOST"
+            ])
+        );
+    }
+
+    #[test]
+    fn reports_missing_variables() {
+        let value = json!({
+            "outbounds": [{ "server": "${SWORD_TEST_MISSING_A}" }],
+            "a/b": "x ${SWORD_TEST_MISSING_B}",
+        });
+        let err = render(value, json!({}), None).unwrap_err();
+        let missing = err.downcast::<MissingVariables>().unwrap().0;
+        let mut found = missing
+            .iter()
+            .map(|m| (m.name.as_str(), m.pointer.as_str()))
+            .collect::<Vec<_>>();
+        found.sort();
+        assert_eq!(
+            found,
+            vec![
+                ("SWORD_TEST_MISSING_A", "/outbounds/0/server"),
+                ("SWORD_TEST_MISSING_B", "/a~1b"),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolves_secrets_only_when_given() {
+        let value = json!({ "password": "${secret:pw}", "url": "ss://${secret:pw}@host" });
+
+        let kept = render(value.clone(), json!({}), None).unwrap();
+        assert_eq!(kept, value);
+
+        let secrets = BTreeMap::from([("pw".to_string(), "hunter2".to_string())]);
+        let value = render(value, json!({}), Some(&secrets)).unwrap();
+        assert_eq!(
+            value,
+            json!({ "password": "hunter2", "url": "ss://hunter2@host" })
+        );
+
+        let err = render(json!("${secret:other}"), json!({}), Some(&secrets)).unwrap_err();
+        assert!(err.is::<MissingVariables>());
+    }
+}
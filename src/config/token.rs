@@ -0,0 +1,162 @@
+use rand::RngCore;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// 生成的 token 的前缀，便于识别
+const TOKEN_PREFIX: &str = "sw_";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenScope {
+    /// 只读
+    Read,
+    /// 修改配置
+    ConfigWrite,
+    /// 控制核心，例如切换节点、关闭连接
+    CoreControl,
+}
+
+impl TokenScope {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TokenScope::Read => "read",
+            TokenScope::ConfigWrite => "config_write",
+            TokenScope::CoreControl => "core_control",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct IWebToken {
+    pub name: String,
+    /// token 的 sha256，原文只在创建时返回一次
+    pub hash: String,
+    pub scopes: Vec<TokenScope>,
+    pub created_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+}
+
+impl IWebToken {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .map_or(false, |at| at <= chrono::Local::now().timestamp())
+    }
+
+    /// 任意权限都包含只读
+    pub fn allows(&self, scope: TokenScope) -> bool {
+        scope == TokenScope::Read || self.scopes.contains(&scope)
+    }
+}
+
+/// 调用方的身份
+#[derive(Debug, Clone)]
+pub enum Access {
+    /// 未设置 web_secret 和 token，或者使用 web_secret
+    Master,
+    Token(IWebToken),
+}
+
+impl Access {
+    pub fn allows(&self, scope: TokenScope) -> bool {
+        match self {
+            Access::Master => true,
+            Access::Token(token) => token.allows(scope),
+        }
+    }
+
+    pub fn is_master(&self) -> bool {
+        matches!(self, Access::Master)
+    }
+}
+
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("{TOKEN_PREFIX}{}", hex(&bytes))
+}
+
+pub fn hash_token(token: &str) -> String {
+    hex(&Sha256::digest(token.as_bytes()))
+}
+
+/// 比较耗时与内容无关，避免通过响应时间猜测 token
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(scopes: Vec<TokenScope>, expires_at: Option<i64>) -> IWebToken {
+        IWebToken {
+            name: "dashboard".into(),
+            hash: hash_token("sw_test"),
+            scopes,
+            created_at: 0,
+            expires_at,
+        }
+    }
+
+    #[test]
+    fn generates_unique_tokens() {
+        let a = generate_token();
+        let b = generate_token();
+        assert!(a.starts_with(TOKEN_PREFIX));
+        assert_eq!(a.len(), TOKEN_PREFIX.len() + 64);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn hashes_tokens() {
+        // sha256("abc")
+        assert_eq!(
+            hash_token("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_ne!(hash_token("abc"), hash_token("abd"));
+    }
+
+    #[test]
+    fn compares_in_constant_time() {
+        assert!(constant_time_eq(b"same", b"same"));
+        assert!(!constant_time_eq(b"same", b"samf"));
+        assert!(!constant_time_eq(b"same", b"sam"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    #[test]
+    fn checks_scopes() {
+        let read = token(vec![TokenScope::Read], None);
+        assert!(read.allows(TokenScope::Read));
+        assert!(!read.allows(TokenScope::ConfigWrite));
+        assert!(!read.allows(TokenScope::CoreControl));
+
+        let control = Access::Token(token(vec![TokenScope::CoreControl], None));
+        assert!(control.allows(TokenScope::Read));
+        assert!(control.allows(TokenScope::CoreControl));
+        assert!(!control.allows(TokenScope::ConfigWrite));
+        assert!(!control.is_master());
+
+        assert!(Access::Master.allows(TokenScope::ConfigWrite));
+        assert!(Access::Master.is_master());
+    }
+
+    #[test]
+    fn checks_expiry() {
+        let now = chrono::Local::now().timestamp();
+        assert!(!token(vec![], None).is_expired());
+        assert!(!token(vec![], Some(now + 60)).is_expired());
+        assert!(token(vec![], Some(now)).is_expired());
+        assert!(token(vec![], Some(now - 60)).is_expired());
+    }
+}
@@ -24,9 +24,11 @@ fn main() {
             notify_log_err!(service::Core::global().run_core());
             notify_log_err!(service::Web::global().run_web(&app_handle));
 
-            let _ = app_handle
-                .tray_handle()
-                .set_menu(service::Tray::tray_menu());
+            notify_log_err!(service::Tray::set_menu(&app_handle));
+            service::Tray::spawn_refresh(&app_handle);
+            service::Tray::spawn_events(&app_handle);
+            service::Traffic::spawn_stream(&app_handle);
+            service::Usage::spawn_recorder();
             Ok(())
         })
         .system_tray(SystemTray::new())
@@ -37,5 +39,9 @@ fn main() {
     #[cfg(target_os = "macos")]
     app.set_activation_policy(tauri::ActivationPolicy::Accessory);
 
-    app.run(|_, _| {});
+    app.run(|_, event| {
+        if let tauri::RunEvent::Exit = event {
+            service::Core::global().stop_core();
+        }
+    });
 }
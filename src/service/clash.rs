@@ -0,0 +1,374 @@
+use crate::{config::ISingBox, utils::dirs};
+use anyhow::{anyhow, bail, Result};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use reqwest::{header::HeaderMap, Client, Method, RequestBuilder};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// clash api 支持的路由模式
+pub const CLASH_MODES: [&str; 3] = ["Rule", "Global", "Direct"];
+
+/// 不区分大小写地匹配路由模式
+pub fn parse_mode(mode: &str) -> Option<&'static str> {
+    CLASH_MODES
+        .into_iter()
+        .find(|m| m.eq_ignore_ascii_case(mode))
+}
+
+/// sing-box 的 clash api 客户端
+#[derive(Debug, Clone)]
+pub struct ClashApi {
+    base: String,
+    secret: Option<String>,
+    client: Client,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IClashVersion {
+    pub version: String,
+    #[serde(default)]
+    pub premium: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct IProxy {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub proxy_type: String,
+    /// 分组当前选中的节点
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub now: Option<String>,
+    /// 分组内的全部节点
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub all: Option<Vec<String>>,
+    #[serde(default)]
+    pub history: Vec<IDelayHistory>,
+    #[serde(default)]
+    pub udp: bool,
+}
+
+impl IProxy {
+    /// 可以手动切换节点的分组
+    pub fn is_selector(&self) -> bool {
+        self.proxy_type.eq_ignore_ascii_case("selector")
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct IDelayHistory {
+    pub time: String,
+    pub delay: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IProxies {
+    proxies: HashMap<String, IProxy>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IDelay {
+    delay: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct IConnections {
+    #[serde(default)]
+    pub download_total: u64,
+    #[serde(default)]
+    pub upload_total: u64,
+    #[serde(default)]
+    pub connections: Vec<IConnection>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct IConnection {
+    pub id: String,
+    pub metadata: IConnectionMeta,
+    pub upload: u64,
+    pub download: u64,
+    pub start: String,
+    #[serde(default)]
+    pub chains: Vec<String>,
+    #[serde(default)]
+    pub rule: String,
+    #[serde(default)]
+    pub rule_payload: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct IConnectionMeta {
+    #[serde(default)]
+    pub network: String,
+    #[serde(default, rename = "type")]
+    pub conn_type: String,
+    #[serde(default, rename = "sourceIP")]
+    pub source_ip: String,
+    #[serde(default)]
+    pub source_port: String,
+    #[serde(default, rename = "destinationIP")]
+    pub destination_ip: String,
+    #[serde(default)]
+    pub destination_port: String,
+    #[serde(default)]
+    pub host: String,
+    #[serde(default)]
+    pub process_path: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ITraffic {
+    /// 每秒上传字节数
+    pub up: u64,
+    /// 每秒下载字节数
+    pub down: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IClashConfigs {
+    pub mode: String,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, Value>,
+}
+
+/// /traffic 返回的数据流，每秒一条
+pub struct TrafficStream {
+    response: reqwest::Response,
+    buffer: Vec<u8>,
+}
+
+impl TrafficStream {
+    pub async fn next(&mut self) -> Result<Option<ITraffic>> {
+        loop {
+            if let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..=end).collect();
+                if line.iter().all(|b| b.is_ascii_whitespace()) {
+                    continue;
+                }
+                return Ok(Some(serde_json::from_slice(&line)?));
+            }
+
+            match self.response.chunk().await? {
+                Some(chunk) => self.buffer.extend_from_slice(&chunk),
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+fn encode(segment: &str) -> String {
+    utf8_percent_encode(segment, NON_ALPHANUMERIC).to_string()
+}
+
+/// 本机访问 external_controller 使用的 host:port
+///
+/// 监听所有地址时通过本机回环访问，域名如 localhost:9090 原样使用。
+pub fn controller_addr(external_controller: &str) -> Result<String> {
+    let controller = external_controller.trim();
+    if let Ok(mut addr) = controller.parse::<SocketAddr>() {
+        if addr.ip().is_unspecified() {
+            match addr {
+                SocketAddr::V4(_) => addr.set_ip([127, 0, 0, 1].into()),
+                SocketAddr::V6(_) => addr.set_ip(std::net::Ipv6Addr::LOCALHOST.into()),
+            }
+        }
+        return Ok(addr.to_string());
+    }
+
+    match controller.rsplit_once(':') {
+        Some(("", port)) if port.parse::<u16>().is_ok() => Ok(format!("127.0.0.1:{port}")),
+        Some((host, port)) if port.parse::<u16>().is_ok() && !host.contains(['/', '@']) => {
+            Ok(controller.to_string())
+        }
+        _ => bail!("invalid external_controller \"{external_controller}\""),
+    }
+}
+
+impl ClashApi {
+    pub fn new(external_controller: &str, secret: Option<String>) -> Result<ClashApi> {
+        let addr = controller_addr(external_controller)?;
+
+        Ok(ClashApi {
+            base: format!("http://{addr}"),
+            secret: secret.filter(|s| !s.is_empty()),
+            client: Client::new(),
+        })
+    }
+
+    /// 读取正在运行的 sing-box 配置中的 external_controller 和 secret
+    pub fn current() -> Result<ClashApi> {
+        let sing_box = ISingBox::read_file(&dirs::sing_box_path())?;
+        let clash = sing_box
+            .experimental
+            .and_then(|exp| exp.clash_api)
+            .ok_or_else(|| anyhow!("clash api is not enabled"))?;
+        if clash.external_controller.is_empty() {
+            bail!("clash api is not enabled");
+        }
+        ClashApi::new(&clash.external_controller, clash.secret)
+    }
+
+    /// 对外访问的地址，如 http://127.0.0.1:9090
+    /// websocket 的地址，clash api 在握手时从 token 参数读取密钥
+    pub fn ws_url(&self, path: &str, query: &str) -> String {
+        let base = self.base.replacen("http", "ws", 1);
+        let mut query = query.to_string();
+        if let Some(secret) = &self.secret {
+            if !query.is_empty() {
+                query.push('&');
+            }
+            query.push_str(&format!("token={}", encode(secret)));
+        }
+        match query.is_empty() {
+            true => format!("{base}{path}"),
+            false => format!("{base}{path}?{query}"),
+        }
+    }
+
+    /// 原样转发请求，不设置超时以支持流式的响应
+    pub async fn forward(
+        &self,
+        method: Method,
+        path: &str,
+        query: &str,
+        headers: HeaderMap,
+        body: Vec<u8>,
+    ) -> Result<reqwest::Response> {
+        let path = match query.is_empty() {
+            true => path.to_string(),
+            false => format!("{path}?{query}"),
+        };
+        let builder = self.request(method, &path).headers(headers).body(body);
+        Ok(builder.send().await?)
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let builder = self.client.request(method, format!("{}{path}", self.base));
+        match &self.secret {
+            Some(secret) => builder.bearer_auth(secret),
+            None => builder,
+        }
+    }
+
+    async fn send(&self, builder: RequestBuilder) -> Result<reqwest::Response> {
+        Ok(builder
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?)
+    }
+
+    pub async fn version(&self) -> Result<IClashVersion> {
+        let res = self.send(self.request(Method::GET, "/version")).await?;
+        Ok(res.json().await?)
+    }
+
+    /// 全部节点和分组
+    pub async fn proxies(&self) -> Result<HashMap<String, IProxy>> {
+        let res = self.send(self.request(Method::GET, "/proxies")).await?;
+        Ok(res.json::<IProxies>().await?.proxies)
+    }
+
+    /// 全部的 selector 分组，按名字排序
+    pub async fn groups(&self) -> Result<Vec<IProxy>> {
+        let mut groups: Vec<IProxy> = self
+            .proxies()
+            .await?
+            .into_values()
+            .filter(|p| p.is_selector() && p.all.is_some())
+            .collect();
+        groups.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(groups)
+    }
+
+    /// 切换 selector 分组选中的节点
+    pub async fn select(&self, group: &str, name: &str) -> Result<()> {
+        let path = format!("/proxies/{}", encode(group));
+        let builder = self
+            .request(Method::PUT, &path)
+            .json(&json!({ "name": name }));
+        self.send(builder).await?;
+        Ok(())
+    }
+
+    /// 测试节点延迟，返回毫秒
+    pub async fn delay(&self, name: &str, url: &str, timeout: Duration) -> Result<u64> {
+        let path = format!("/proxies/{}/delay", encode(name));
+        let builder = self
+            .request(Method::GET, &path)
+            .query(&[("url", url), ("timeout", &timeout.as_millis().to_string())])
+            .timeout(timeout + REQUEST_TIMEOUT);
+        let res = builder.send().await?.error_for_status()?;
+        Ok(res.json::<IDelay>().await?.delay)
+    }
+
+    pub async fn connections(&self) -> Result<IConnections> {
+        let res = self.send(self.request(Method::GET, "/connections")).await?;
+        Ok(res.json().await?)
+    }
+
+    pub async fn close_connection(&self, id: &str) -> Result<()> {
+        let path = format!("/connections/{}", encode(id));
+        self.send(self.request(Method::DELETE, &path)).await?;
+        Ok(())
+    }
+
+    pub async fn close_connections(&self) -> Result<()> {
+        self.send(self.request(Method::DELETE, "/connections"))
+            .await?;
+        Ok(())
+    }
+
+    /// 实时流量，连接会一直保持
+    pub async fn traffic(&self) -> Result<TrafficStream> {
+        let response = self
+            .request(Method::GET, "/traffic")
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(TrafficStream {
+            response,
+            buffer: vec![],
+        })
+    }
+
+    pub async fn configs(&self) -> Result<IClashConfigs> {
+        let res = self.send(self.request(Method::GET, "/configs")).await?;
+        Ok(res.json().await?)
+    }
+
+    pub async fn patch_configs(&self, value: &Value) -> Result<()> {
+        let builder = self.request(Method::PATCH, "/configs").json(value);
+        self.send(builder).await?;
+        Ok(())
+    }
+
+    /// 切换路由模式 Rule / Global / Direct
+    pub async fn set_mode(&self, mode: &str) -> Result<()> {
+        self.patch_configs(&json!({ "mode": mode })).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_controller_addr() {
+        let addr = |s: &str| controller_addr(s).ok();
+        assert_eq!(addr("127.0.0.1:9090").as_deref(), Some("127.0.0.1:9090"));
+        assert_eq!(addr("0.0.0.0:9090").as_deref(), Some("127.0.0.1:9090"));
+        assert_eq!(addr("[::]:9090").as_deref(), Some("[::1]:9090"));
+        assert_eq!(addr(":9090").as_deref(), Some("127.0.0.1:9090"));
+        assert_eq!(addr("localhost:9090").as_deref(), Some("localhost:9090"));
+        assert_eq!(addr("localhost"), None);
+        assert_eq!(addr("localhost:http"), None);
+        assert_eq!(addr("user@host:9090"), None);
+    }
+}
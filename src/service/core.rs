@@ -1,4 +1,11 @@
-use crate::{config::Sword, utils::dirs};
+use crate::{
+    config::{self, Sword},
+    service,
+    utils::{
+        dirs,
+        events::{self, SwordEvent},
+    },
+};
 use anyhow::{bail, Result};
 use once_cell::sync::OnceCell;
 use parking_lot::RwLock;
@@ -37,35 +44,45 @@ impl Core {
 
     /// 启动核心
     pub fn run_core(&self) -> Result<()> {
+        Sword::global().save_runtime_sing_box()?;
+        crate::log_err!(service::sync_external_ui());
         self.check_config()?;
 
         let mut core_handler = self.core_handler.write();
 
-        core_handler.take().map(|ch| {
+        if let Some(ch) = core_handler.take() {
             let _ = ch.kill();
-        });
+            events::publish(SwordEvent::CoreStopped);
+        }
 
         let config_dir = dirs::sing_box_dir();
         let config_dir = dirs::path_to_str(&config_dir)?;
         let core_path = current_core_path()?;
         let cmd = Command::new_sidecar(&core_path)?;
 
-        #[allow(unused_mut)]
         let (mut rx, cmd_child) = cmd
             .args(["run", "-c", "config.json", "-D", config_dir])
             .spawn()?;
 
+        let pid = cmd_child.pid();
         *core_handler = Some(cmd_child);
 
         log::info!(target: "app", "run core {core_path}");
+        let core = Sword::global().core_name().unwrap_or_default();
+        events::publish(SwordEvent::CoreStarted { core });
 
-        #[cfg(feature = "stdout-log")]
         tauri::async_runtime::spawn(async move {
             while let Some(event) = rx.recv().await {
                 match event {
-                    CommandEvent::Terminated(_) => break,
+                    CommandEvent::Terminated(payload) => {
+                        Core::global().on_terminated(pid, payload.code);
+                        break;
+                    }
+                    #[cfg(feature = "stdout-log")]
                     CommandEvent::Error(err) => log::error!("{err}"),
+                    #[cfg(feature = "stdout-log")]
                     CommandEvent::Stdout(line) => log::info!("{line}"),
+                    #[cfg(feature = "stdout-log")]
                     CommandEvent::Stderr(line) => log::info!("{line}"),
                     _ => {}
                 }
@@ -75,6 +92,25 @@ impl Core {
         Ok(())
     }
 
+    /// 退出应用前停止核心，之后的退出不会被当作崩溃
+    pub fn stop_core(&self) {
+        if let Some(ch) = self.core_handler.write().take() {
+            let _ = ch.kill();
+            events::publish(SwordEvent::CoreStopped);
+        }
+    }
+
+    /// 核心退出，不是由重启、更换核心或退出应用引起时视为崩溃
+    fn on_terminated(&self, pid: u32, code: Option<i32>) {
+        let mut core_handler = self.core_handler.write();
+        if core_handler.as_ref().map_or(false, |ch| ch.pid() == pid) {
+            *core_handler = None;
+            drop(core_handler);
+            log::error!(target: "app", "core exited unexpectedly with code {code:?}");
+            events::publish(SwordEvent::CoreCrashed { code });
+        }
+    }
+
     /// 获取所有可执行的文件
     pub fn list_core() -> Result<Vec<String>> {
         let core_dir = dirs::core_dir()?;
@@ -106,12 +142,17 @@ impl Core {
         }
 
         let sword = Sword::global();
+        let writes = sword.writes.lock();
         let mut config = sword.config.write();
-        config.core_name = Some(name);
+        config.core_name = Some(name.clone());
+        let etag = config::etag(&*config)?;
         drop(config);
         sword.save_config()?;
+        drop(writes);
+        events::publish(SwordEvent::ConfigChanged { etag });
 
         self.run_core()?;
+        events::publish(SwordEvent::CoreSwitched { core: name });
         Ok(())
     }
 }
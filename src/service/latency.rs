@@ -0,0 +1,264 @@
+use crate::{
+    config::{self, IOutbound},
+    service::ClashApi,
+};
+use anyhow::{anyhow, Result};
+use once_cell::sync::OnceCell;
+use parking_lot::RwLock;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::{net::TcpStream, sync::Semaphore};
+use tokio_rustls::{
+    rustls::{
+        self,
+        client::{ServerCertVerified, ServerCertVerifier},
+        Certificate, ClientConfig, ServerName,
+    },
+    TlsConnector,
+};
+
+const DEFAULT_TEST_URL: &str = "https://www.gstatic.com/generate_204";
+const DEFAULT_TIMEOUT: u64 = 5000;
+const DEFAULT_CONCURRENCY: usize = 8;
+
+/// 不经过代理、无法直接测试的出站类型
+const SKIP_TYPES: [&str; 3] = ["block", "dns", "direct"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LatencyMethod {
+    /// 通过 clash api 的 delay 接口测试
+    Clash,
+    /// 核心未运行时直接连接节点的服务器
+    Tcp,
+    /// 核心未运行时与启用了 tls 的节点完成握手
+    Tls,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ILatency {
+    /// 毫秒，测试失败时为空
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delay: Option<u64>,
+    pub method: LatencyMethod,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub tested_at: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Latency {
+    /// 最近一次测试的结果，以出站的 tag 为键
+    pub results: Arc<RwLock<HashMap<String, ILatency>>>,
+}
+
+/// 一个待测试的出站
+enum Target {
+    Clash(ClashApi, String),
+    /// 服务器、端口和 tls 的 server_name
+    Tcp(String, u16, Option<String>),
+}
+
+impl Latency {
+    pub fn global() -> &'static Latency {
+        static SERVICE: OnceCell<Latency> = OnceCell::new();
+        SERVICE.get_or_init(|| Latency {
+            results: Arc::new(RwLock::new(HashMap::new())),
+        })
+    }
+
+    /// 测试全部出站，核心在运行时走 clash api，否则直接连接服务器
+    pub async fn test_all(&self) -> Result<HashMap<String, ILatency>> {
+        let (url, timeout, concurrency) = {
+            let config = config::Sword::global().config.read();
+            (
+                config
+                    .latency_test_url
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_TEST_URL.into()),
+                Duration::from_millis(config.latency_timeout.unwrap_or(DEFAULT_TIMEOUT)),
+                config
+                    .latency_concurrency
+                    .unwrap_or(DEFAULT_CONCURRENCY)
+                    .max(1),
+            )
+        };
+
+        // 只需要 tag 和服务器地址，不解密密文
+        let outbounds = match config::Sword::global().render_sing_box(false)?.outbounds {
+            Some(Value::Array(list)) => list,
+            _ => vec![],
+        };
+        let outbounds = outbounds
+            .into_iter()
+            .filter_map(|value| serde_json::from_value::<IOutbound>(value).ok());
+
+        let clash = match ClashApi::current() {
+            Ok(clash) if clash.version().await.is_ok() => Some(clash),
+            _ => None,
+        };
+
+        let targets = outbounds
+            .filter(|o| !SKIP_TYPES.contains(&o.outbound_type.as_str()))
+            .filter_map(|o| {
+                let tls = tls_server_name(&o);
+                let tag = o.tag?;
+                let target = match &clash {
+                    Some(clash) => Target::Clash(clash.clone(), tag.clone()),
+                    None => Target::Tcp(o.server?, o.server_port?, tls),
+                };
+                Some((tag, target))
+            })
+            .collect::<Vec<_>>();
+
+        let semaphore = Arc::new(Semaphore::new(concurrency));
+        let handles = targets
+            .into_iter()
+            .map(|(tag, target)| {
+                let semaphore = semaphore.clone();
+                let url = url.clone();
+                tauri::async_runtime::spawn(async move {
+                    let _permit = semaphore.acquire_owned().await;
+                    (tag, test_target(target, &url, timeout).await)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut results = HashMap::new();
+        for handle in handles {
+            if let Ok((tag, latency)) = handle.await {
+                results.insert(tag, latency);
+            }
+        }
+
+        *self.results.write() = results.clone();
+        Ok(results)
+    }
+}
+
+async fn test_target(target: Target, url: &str, timeout: Duration) -> ILatency {
+    let (method, result) = match target {
+        Target::Clash(clash, name) => {
+            (LatencyMethod::Clash, clash.delay(&name, url, timeout).await)
+        }
+        Target::Tcp(server, port, None) => {
+            (LatencyMethod::Tcp, tcp_delay(&server, port, timeout).await)
+        }
+        Target::Tcp(server, port, Some(name)) => (
+            LatencyMethod::Tls,
+            tls_delay(&server, port, &name, timeout).await,
+        ),
+    };
+
+    let (delay, error) = match result {
+        Ok(delay) => (Some(delay), None),
+        Err(err) => (None, Some(err.to_string())),
+    };
+    ILatency {
+        delay,
+        method,
+        error,
+        tested_at: chrono::Local::now().timestamp(),
+    }
+}
+
+/// 测量和服务器建立 tcp 连接的耗时
+async fn tcp_delay(server: &str, port: u16, timeout: Duration) -> Result<u64> {
+    let start = Instant::now();
+    tokio::time::timeout(timeout, TcpStream::connect((server, port)))
+        .await
+        .map_err(|_| anyhow!("timeout"))??;
+    Ok(start.elapsed().as_millis() as u64)
+}
+
+/// 启用了 tls 的出站握手时使用的名字，未设置 server_name 时使用服务器地址
+fn tls_server_name(outbound: &IOutbound) -> Option<String> {
+    let tls = outbound.extra.get("tls")?;
+    if tls.get("enabled").and_then(Value::as_bool) != Some(true) {
+        return None;
+    }
+    tls.get("server_name")
+        .and_then(Value::as_str)
+        .map(String::from)
+        .or_else(|| outbound.server.clone())
+}
+
+/// 只测量握手的耗时，不发送数据，不需要校验证书
+struct AcceptAnyCert;
+
+impl ServerCertVerifier for AcceptAnyCert {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// 测量和服务器建立 tcp 连接并完成 tls 握手的耗时
+async fn tls_delay(server: &str, port: u16, name: &str, timeout: Duration) -> Result<u64> {
+    let name = ServerName::try_from(name).map_err(|_| anyhow!("invalid server name {name}"))?;
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyCert))
+        .with_no_client_auth();
+    let connector = TlsConnector::from(Arc::new(config));
+
+    let start = Instant::now();
+    let handshake = async {
+        let stream = TcpStream::connect((server, port)).await?;
+        connector.connect(name, stream).await
+    };
+    tokio::time::timeout(timeout, handshake)
+        .await
+        .map_err(|_| anyhow!("timeout"))??;
+    Ok(start.elapsed().as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn outbound(value: Value) -> IOutbound {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn uses_tls_only_when_enabled() {
+        let plain = outbound(json!({ "type": "shadowsocks", "server": "1.2.3.4" }));
+        assert_eq!(tls_server_name(&plain), None);
+
+        let disabled = outbound(json!({
+            "type": "trojan",
+            "server": "1.2.3.4",
+            "tls": { "enabled": false, "server_name": "example.com" },
+        }));
+        assert_eq!(tls_server_name(&disabled), None);
+
+        let named = outbound(json!({
+            "type": "trojan",
+            "server": "1.2.3.4",
+            "tls": { "enabled": true, "server_name": "example.com" },
+        }));
+        assert_eq!(tls_server_name(&named), Some("example.com".into()));
+
+        let unnamed = outbound(json!({
+            "type": "vless",
+            "server": "example.org",
+            "tls": { "enabled": true },
+        }));
+        assert_eq!(tls_server_name(&unnamed), Some("example.org".into()));
+    }
+}
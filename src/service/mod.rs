@@ -1,7 +1,17 @@
+mod clash;
 mod core;
+mod latency;
+mod traffic;
 mod tray;
+mod ui;
+mod usage;
 mod web;
 
 pub use self::core::*;
+pub use clash::*;
+pub use latency::*;
+pub use traffic::*;
 pub use tray::*;
+pub use ui::*;
+pub use usage::*;
 pub use web::*;
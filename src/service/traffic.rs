@@ -0,0 +1,96 @@
+use crate::service::{ClashApi, ITraffic, Tray};
+use anyhow::Result;
+use once_cell::sync::OnceCell;
+use parking_lot::RwLock;
+use std::{sync::Arc, time::Duration};
+use tauri::AppHandle;
+use tokio::sync::broadcast;
+
+/// clash api 断开后重连的间隔
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone)]
+pub struct Traffic {
+    /// 最近一次的速率，核心未运行时为空
+    pub current: Arc<RwLock<Option<ITraffic>>>,
+
+    pub sender: broadcast::Sender<ITraffic>,
+}
+
+impl Traffic {
+    pub fn global() -> &'static Traffic {
+        static SERVICE: OnceCell<Traffic> = OnceCell::new();
+        SERVICE.get_or_init(|| Traffic {
+            current: Arc::new(RwLock::new(None)),
+            sender: broadcast::channel(16).0,
+        })
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ITraffic> {
+        self.sender.subscribe()
+    }
+
+    /// 托盘中显示的速率
+    pub fn title(&self) -> String {
+        match *self.current.read() {
+            Some(traffic) => format!(
+                "↑ {}  ↓ {}",
+                format_rate(traffic.up),
+                format_rate(traffic.down)
+            ),
+            None => "↑ -  ↓ -".into(),
+        }
+    }
+
+    /// 持续读取 /traffic，断开后自动重连
+    pub fn spawn_stream(app_handle: &AppHandle) {
+        let app_handle = app_handle.clone();
+        tauri::async_runtime::spawn(async move {
+            let traffic = Traffic::global();
+            loop {
+                if let Err(err) = traffic.stream(&app_handle).await {
+                    log::debug!(target: "app", "traffic stream: {err}");
+                }
+
+                *traffic.current.write() = None;
+                traffic.update_tray(&app_handle);
+                tokio::time::sleep(RECONNECT_INTERVAL).await;
+            }
+        });
+    }
+
+    async fn stream(&self, app_handle: &AppHandle) -> Result<()> {
+        let mut stream = ClashApi::current()?.traffic().await?;
+        while let Some(traffic) = stream.next().await? {
+            *self.current.write() = Some(traffic);
+            // 没有订阅者时发送会失败，忽略即可
+            let _ = self.sender.send(traffic);
+            self.update_tray(app_handle);
+        }
+        Ok(())
+    }
+
+    fn update_tray(&self, app_handle: &AppHandle) {
+        if !*Tray::global().has_menu.read() {
+            return;
+        }
+        let item = app_handle.tray_handle().get_item("traffic");
+        crate::log_err!(item.set_title(self.title()));
+    }
+}
+
+fn format_rate(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    match unit {
+        0 => format!("{bytes} B/s"),
+        _ => format!("{value:.1} {}/s", UNITS[unit]),
+    }
+}
@@ -1,23 +1,195 @@
 use crate::{
     config::{self, ISingBox},
     notify_err, service,
-    utils::{self, dirs, init},
+    utils::{
+        self, dirs,
+        events::{self, SwordEvent},
+        init,
+    },
 };
 use anyhow::Result;
 use once_cell::sync::OnceCell;
-use std::net::SocketAddr;
+use parking_lot::RwLock;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 use tauri::{
-    AppHandle, CustomMenuItem, SystemTrayEvent, SystemTrayMenu, SystemTrayMenuItem,
-    SystemTraySubmenu,
+    api::notification::Notification, AppHandle, CustomMenuItem, SystemTrayEvent, SystemTrayMenu,
+    SystemTrayMenuItem, SystemTraySubmenu,
 };
+use tokio::sync::broadcast::error::RecvError;
+
+/// 定时刷新分组的间隔
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
 
 #[derive(Debug, Clone)]
-pub struct Tray {}
+pub struct Tray {
+    /// clash api 中的 selector 分组
+    pub groups: Arc<RwLock<Vec<service::IProxy>>>,
+
+    /// 菜单项 proxy_{index} 对应的 (分组, 节点)
+    pub proxy_items: Arc<RwLock<Vec<(String, String)>>>,
+
+    /// 核心当前的路由模式
+    pub mode: Arc<RwLock<Option<String>>>,
+
+    /// 菜单中显示的节点延迟
+    pub latency: Arc<RwLock<HashMap<String, u64>>>,
+
+    /// 菜单设置成功后才能获取其中的菜单项，否则 get_item 会 panic
+    pub has_menu: Arc<RwLock<bool>>,
+}
 
 impl Tray {
     pub fn global() -> &'static Tray {
         static SERVICE: OnceCell<Tray> = OnceCell::new();
-        SERVICE.get_or_init(|| Tray {})
+        SERVICE.get_or_init(|| Tray {
+            groups: Arc::new(RwLock::new(vec![])),
+            proxy_items: Arc::new(RwLock::new(vec![])),
+            mode: Arc::new(RwLock::new(None)),
+            latency: Arc::new(RwLock::new(HashMap::new())),
+            has_menu: Arc::new(RwLock::new(false)),
+        })
+    }
+
+    /// 重新生成并设置托盘菜单
+    pub fn set_menu(app_handle: &AppHandle) -> tauri::Result<()> {
+        app_handle.tray_handle().set_menu(Tray::tray_menu())?;
+        *Tray::global().has_menu.write() = true;
+        Ok(())
+    }
+
+    /// 路由模式的子菜单，核心未运行时不显示
+    fn mode_menu(&self) -> Option<SystemTraySubmenu> {
+        let current = self.mode.read().clone()?;
+        let mut menu = SystemTrayMenu::new();
+        for mode in service::CLASH_MODES {
+            let item = CustomMenuItem::new(format!("mode_{mode}"), mode);
+            let item = match mode.eq_ignore_ascii_case(&current) {
+                true => item.selected(),
+                false => item,
+            };
+            menu = menu.add_item(item);
+        }
+        Some(SystemTraySubmenu::new("Mode", menu))
+    }
+
+    /// 每个 selector 分组一个子菜单，选中当前的节点
+    fn proxy_menu(&self) -> Vec<SystemTraySubmenu> {
+        let groups = self.groups.read();
+        let latency = self.latency.read();
+        let mut proxy_items = self.proxy_items.write();
+        proxy_items.clear();
+
+        groups
+            .iter()
+            .map(|group| {
+                let mut menu = SystemTrayMenu::new();
+                for name in group.all.iter().flatten() {
+                    let id = format!("proxy_{}", proxy_items.len());
+                    let title = match latency.get(name) {
+                        Some(delay) => format!("{name} — {delay}ms"),
+                        None => name.clone(),
+                    };
+                    let item = CustomMenuItem::new(id, title);
+                    let item = match group.now.as_ref() == Some(name) {
+                        true => item.selected(),
+                        false => item,
+                    };
+                    menu = menu.add_item(item);
+                    proxy_items.push((group.name.clone(), name.clone()));
+                }
+                SystemTraySubmenu::new(&group.name, menu)
+            })
+            .collect()
+    }
+
+    /// 从 clash api 拉取分组和路由模式，有变化时更新菜单
+    pub async fn refresh(app_handle: &AppHandle) {
+        let (groups, mode) = match service::ClashApi::current() {
+            Ok(clash) => (
+                clash.groups().await.unwrap_or_default(),
+                clash.configs().await.ok().map(|c| c.mode),
+            ),
+            Err(_) => (vec![], None),
+        };
+
+        let latency = service::Latency::global()
+            .results
+            .read()
+            .iter()
+            .filter_map(|(name, r)| r.delay.map(|delay| (name.clone(), delay)))
+            .collect::<HashMap<_, _>>();
+
+        let tray = Tray::global();
+        let key = |groups: &[service::IProxy]| {
+            groups
+                .iter()
+                .map(|g| (g.name.clone(), g.now.clone(), g.all.clone()))
+                .collect::<Vec<_>>()
+        };
+        if key(&tray.groups.read()) == key(&groups)
+            && *tray.mode.read() == mode
+            && *tray.latency.read() == latency
+        {
+            return;
+        }
+
+        *tray.groups.write() = groups;
+        *tray.mode.write() = mode;
+        *tray.latency.write() = latency;
+        crate::log_err!(Tray::set_menu(app_handle));
+    }
+
+    /// 核心重启后需要等待 clash api 就绪
+    pub fn refresh_later(app_handle: &AppHandle) {
+        let app_handle = app_handle.clone();
+        tauri::async_runtime::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            Tray::refresh(&app_handle).await;
+        });
+    }
+
+    /// 定时刷新分组
+    pub fn spawn_refresh(app_handle: &AppHandle) {
+        let app_handle = app_handle.clone();
+        tauri::async_runtime::spawn(async move {
+            loop {
+                Tray::refresh(&app_handle).await;
+                tokio::time::sleep(REFRESH_INTERVAL).await;
+            }
+        });
+    }
+
+    /// 核心或配置变化时更新菜单，核心意外退出时通知
+    pub fn spawn_events(app_handle: &AppHandle) {
+        let app_handle = app_handle.clone();
+        tauri::async_runtime::spawn(async move {
+            let mut receiver = events::subscribe();
+            loop {
+                let event = match receiver.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+                match event {
+                    SwordEvent::CoreStarted { .. } | SwordEvent::SingBoxChanged { .. } => {
+                        Tray::refresh_later(&app_handle)
+                    }
+                    SwordEvent::CoreSwitched { .. } => {
+                        crate::log_err!(Tray::set_menu(&app_handle));
+                    }
+                    SwordEvent::CoreCrashed { code } => {
+                        let code = code.map_or("unknown".into(), |c| c.to_string());
+                        let _ = Notification::new(utils::IDENTIFIER)
+                            .title("Core Exited")
+                            .body(format!("the core exited unexpectedly with code {code}"))
+                            .show();
+                        Tray::refresh(&app_handle).await;
+                    }
+                    _ => {}
+                }
+            }
+        });
     }
 
     pub fn tray_menu() -> SystemTrayMenu {
@@ -45,31 +217,67 @@ impl Tray {
             }
         }
 
+        let web_status = service::Web::global().status.read().clone();
+        let web_title = match (web_status.listening.first(), web_status.errors.is_empty()) {
+            (Some(addr), true) => format!("Server: {addr}"),
+            (Some(addr), false) => format!("Server: {addr} (with errors)"),
+            (None, _) => "Server: not running".into(),
+        };
+        service = service.add_item(CustomMenuItem::new("web_status", web_title).disabled());
+
         let config = SystemTrayMenu::new()
             .add_item(CustomMenuItem::new("open_sword_config", "Sword Config"))
             .add_item(CustomMenuItem::new("open_sing_config", "SingBox Config"))
             .add_item(CustomMenuItem::new("open_core_dir", "Core Dir"))
             .add_item(CustomMenuItem::new("open_logs_dir", "Logs Dir"));
 
-        let about = SystemTrayMenu::new().add_item(
+        let mut about = SystemTrayMenu::new().add_item(
             CustomMenuItem::new("app_version", format!("Version {}", init::app_version()))
                 .disabled(),
         );
+        if let Some(fingerprint) = service::Web::global().status.read().tls_fingerprint.clone() {
+            about = about.add_item(
+                CustomMenuItem::new("tls_fingerprint", format!("TLS SHA-256 {fingerprint}"))
+                    .disabled(),
+            );
+        }
 
-        SystemTrayMenu::new()
+        let traffic = service::Traffic::global().title();
+        let mut menu = SystemTrayMenu::new()
+            .add_item(CustomMenuItem::new("traffic", traffic).disabled())
+            .add_native_item(SystemTrayMenuItem::Separator)
             .add_item(CustomMenuItem::new("dashboard", "Dashboard"))
             .add_item(CustomMenuItem::new("clash_dashboard", "Clash Dashboard"))
-            .add_native_item(SystemTrayMenuItem::Separator)
-            .add_submenu(SystemTraySubmenu::new(
-                "Service",
-                service
-                    .add_item(CustomMenuItem::new("run_core", "Restart Core"))
-                    .add_item(CustomMenuItem::new("run_server", "Restart Server")),
-            ))
-            .add_submenu(SystemTraySubmenu::new("Config", config))
-            .add_submenu(SystemTraySubmenu::new("About", about))
-            .add_native_item(SystemTrayMenuItem::Separator)
-            .add_item(CustomMenuItem::new("quit", "Quit").accelerator("CmdOrControl+Q"))
+            .add_native_item(SystemTrayMenuItem::Separator);
+
+        let tray = Tray::global();
+        let mode_menu = tray.mode_menu();
+        let proxy_menu = tray.proxy_menu();
+        if mode_menu.is_some() || !proxy_menu.is_empty() {
+            if let Some(submenu) = mode_menu {
+                menu = menu.add_submenu(submenu);
+            }
+            for submenu in proxy_menu {
+                menu = menu.add_submenu(submenu);
+            }
+            menu = menu.add_native_item(SystemTrayMenuItem::Separator);
+        }
+
+        menu.add_submenu(SystemTraySubmenu::new(
+            "Service",
+            service
+                .add_item(CustomMenuItem::new("test_latency", "Test Latency"))
+                .add_item(CustomMenuItem::new(
+                    "close_connections",
+                    "Close All Connections",
+                ))
+                .add_item(CustomMenuItem::new("run_core", "Restart Core"))
+                .add_item(CustomMenuItem::new("run_server", "Restart Server")),
+        ))
+        .add_submenu(SystemTraySubmenu::new("Config", config))
+        .add_submenu(SystemTraySubmenu::new("About", about))
+        .add_native_item(SystemTrayMenuItem::Separator)
+        .add_item(CustomMenuItem::new("quit", "Quit").accelerator("CmdOrControl+Q"))
     }
 
     pub fn on_event(&self, app_handle: &AppHandle, id: &str) -> Result<()> {
@@ -77,7 +285,14 @@ impl Tray {
             "dashboard" => {
                 let (port, _, secret, web_ui) = config::Sword::global().web_info();
 
-                let url = web_ui.unwrap_or(format!("http://localhost:{port}"));
+                let scheme = match service::Web::global().is_tls() {
+                    true => "https",
+                    false => "http",
+                };
+                let url = match web_ui {
+                    Some(web_ui) => ui_link(web_ui, port),
+                    None => format!("{scheme}://localhost:{port}"),
+                };
                 let mut link = format!("{url}?server=127.0.0.1&port={port}");
                 if let Some(secret) = secret {
                     link = format!("{link}&token={secret}");
@@ -88,16 +303,29 @@ impl Tray {
                 let path = dirs::sing_box_path();
                 let sing_box = ISingBox::read_file(&path)?;
                 let sword = config::Sword::global();
+                let (port, _, secret, _) = sword.web_info();
                 let config = sword.config.read();
                 let default_url = "https://yacd.haishan.me/";
                 let url = config.clash_ui.clone().unwrap_or(default_url.into());
                 drop(config);
+                let url = ui_link(url, port);
 
                 if let Some(exp) = sing_box.experimental {
                     if let Some(clash) = exp.clash_api {
-                        let socket: SocketAddr = clash.external_controller.parse()?;
-                        let mut link = format!("{url}?host={}&port={}", socket.ip(), socket.port());
-                        if let Some(secret) = clash.secret {
+                        // external_ui 由核心自己在 /ui 下提供
+                        let url = match clash.external_ui {
+                            Some(_) => {
+                                let controller =
+                                    service::controller_addr(&clash.external_controller)?;
+                                format!("http://{controller}/ui/")
+                            }
+                            None => url,
+                        };
+                        // 通过 sword 的 /clash 访问，只需要 sword 的端口和密钥
+                        let mut link =
+                            format!("{url}?hostname=localhost&port={port}&secondaryPath=/clash");
+                        if let Some(secret) = secret.filter(|s| !s.is_empty()) {
+                            let secret = utf8_percent_encode(&secret, NON_ALPHANUMERIC);
                             link = format!("{link}&secret={secret}");
                         }
                         open::that(link)?;
@@ -105,25 +333,91 @@ impl Tray {
                 }
             }
             "run_core" => notify_err!(service::Core::global().run_core())?,
-            "run_server" => notify_err!(service::Web::global().run_web(app_handle))?,
+            "test_latency" => {
+                let app_handle = app_handle.clone();
+                tauri::async_runtime::spawn(async move {
+                    let result = service::Latency::global().test_all().await;
+                    crate::log_err!(notify_err!(result));
+                    Tray::refresh(&app_handle).await;
+                });
+            }
+            "close_connections" => {
+                tauri::async_runtime::spawn(async move {
+                    let result = match service::ClashApi::current() {
+                        Ok(clash) => clash.close_connections().await,
+                        Err(err) => Err(err),
+                    };
+                    crate::log_err!(notify_err!(result));
+                });
+            }
+            "run_server" => {
+                let result = notify_err!(service::Web::global().run_web(app_handle));
+                Tray::set_menu(app_handle)?;
+                result?;
+            }
             "open_sword_config" => utils::open_by_code(&&dirs::sword_config_path())?,
-            "open_sing_config" => utils::open_by_code(&dirs::sing_box_path())?,
+            "open_sing_config" => utils::open_by_code(&dirs::profile_path())?,
             "open_core_dir" => open::that(dirs::core_dir()?)?,
             "open_logs_dir" => open::that(dirs::log_dir())?,
-            "quit" => app_handle.exit(0),
+            "quit" => {
+                service::Core::global().stop_core();
+                app_handle.exit(0);
+            }
             _ => {
                 // 更换核心
                 if id.starts_with("service_core_") {
                     let core = format!("{}", &id[13..]);
 
                     service::Core::global().change_core(core)?;
-                    app_handle.tray_handle().set_menu(Tray::tray_menu())?;
+                }
+
+                // 切换路由模式
+                if let Some(mode) = id.strip_prefix("mode_") {
+                    let mode = mode.to_string();
+                    let app_handle = app_handle.clone();
+                    tauri::async_runtime::spawn(async move {
+                        let result = match service::ClashApi::current() {
+                            Ok(clash) => clash.set_mode(&mode).await,
+                            Err(err) => Err(err),
+                        };
+                        crate::log_err!(notify_err!(result));
+                        Tray::refresh(&app_handle).await;
+                    });
+                }
+
+                // 切换分组的节点
+                if let Some(index) = id.strip_prefix("proxy_") {
+                    let index: usize = index.parse()?;
+                    let item = self.proxy_items.read().get(index).cloned();
+                    if let Some((group, name)) = item {
+                        let app_handle = app_handle.clone();
+                        tauri::async_runtime::spawn(async move {
+                            let result = match service::ClashApi::current() {
+                                Ok(clash) => clash.select(&group, &name).await,
+                                Err(err) => Err(err),
+                            };
+                            crate::log_err!(notify_err!(result));
+                            Tray::refresh(&app_handle).await;
+                        });
+                    }
                 }
             }
         })
     }
 }
 
+/// 已安装的 ui 的名字转换为本地的地址，其余的原样返回
+fn ui_link(ui: String, port: u16) -> String {
+    if !service::has_ui(&ui) {
+        return ui;
+    }
+    let scheme = match service::Web::global().is_tls() {
+        true => "https",
+        false => "http",
+    };
+    format!("{scheme}://localhost:{port}/ui/{ui}/")
+}
+
 pub fn on_system_tray_event(app_handle: &AppHandle, event: SystemTrayEvent) {
     match event {
         SystemTrayEvent::MenuItemClick { id, .. } => {
@@ -0,0 +1,409 @@
+use crate::{
+    config::{ISingBox, InvalidEdit, Sword},
+    utils::dirs,
+};
+use anyhow::{bail, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    io::{self, Cursor, Read},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use zip::ZipArchive;
+
+/// 上传或下载的 ui 压缩包的大小上限
+pub const MAX_UI_BUNDLE: u64 = 64 * 1024 * 1024;
+
+/// 解压后的总大小上限
+const MAX_UI_SIZE: u64 = 256 * 1024 * 1024;
+
+/// 压缩包中的文件数上限
+const MAX_UI_ENTRIES: usize = 10_000;
+
+/// 下载 ui 压缩包的超时
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// external_ui 目录中记录来源的文件，只替换由 sword 写入的目录
+const EXTERNAL_UI_MARKER: &str = ".sword-ui";
+
+/// 安装时写入的压缩包哈希，用于判断 external_ui 是否需要更新
+const BUNDLE_ID: &str = ".sword-bundle";
+
+/// 已安装的 ui，位于 ui 目录下，通过 /ui/<name>/ 访问
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct IUiBundle {
+    pub name: String,
+    /// 是否包含 index.html
+    pub valid: bool,
+}
+
+fn bundle_path(name: &str) -> Result<PathBuf> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+        && !name.starts_with('.');
+    if !valid {
+        return Err(InvalidEdit(format!("invalid ui name \"{name}\"")).into());
+    }
+    Ok(dirs::ui_dir().join(name))
+}
+
+/// 列出已安装的 ui
+pub fn list_ui() -> Result<Vec<IUiBundle>> {
+    let ui_dir = dirs::ui_dir();
+    if !ui_dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut list = fs::read_dir(ui_dir)?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().map_or(false, |f| f.is_dir()))
+        .filter_map(|e| e.file_name().into_string().ok())
+        .filter(|name| !name.starts_with('.'))
+        .map(|name| IUiBundle {
+            valid: dirs::ui_dir().join(&name).join("index.html").exists(),
+            name,
+        })
+        .collect::<Vec<_>>();
+    list.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(list)
+}
+
+/// 是否已安装名为 name 的 ui
+pub fn has_ui(name: &str) -> bool {
+    bundle_path(name).map_or(false, |path| path.join("index.html").exists())
+}
+
+/// 解压 zip 并安装，已存在的同名 ui 会被替换
+///
+/// 解压在阻塞线程中进行，不占用处理请求的线程。
+pub async fn install_zip(name: &str, data: impl AsRef<[u8]> + Send + 'static) -> Result<()> {
+    let name = name.to_string();
+    tauri::async_runtime::spawn_blocking(move || unpack(&name, data.as_ref())).await?
+}
+
+/// 压缩包中只有一个顶层目录时（例如 yacd-gh-pages/），以该目录为根
+fn unpack(name: &str, data: &[u8]) -> Result<()> {
+    let target = bundle_path(name)?;
+    let staging = dirs::ui_dir().join(format!(".{name}.tmp"));
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+    fs::create_dir_all(&staging)?;
+
+    let result = extract(data, &staging).and_then(|_| {
+        let root = bundle_root(&staging)?;
+        if !root.join("index.html").exists() {
+            return Err(InvalidEdit("no index.html found in the ui bundle".into()).into());
+        }
+        fs::write(root.join(BUNDLE_ID), bundle_id(data))?;
+        if target.exists() {
+            fs::remove_dir_all(&target)?;
+        }
+        fs::rename(&root, &target)?;
+        Ok(())
+    });
+
+    let _ = fs::remove_dir_all(&staging);
+    if result.is_ok() {
+        log::info!(target: "app", "installed ui {name}");
+    }
+    result
+}
+
+/// 压缩包的 sha256
+fn bundle_id(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// 下载 zip 并安装
+pub async fn install_url(name: &str, url: &str) -> Result<()> {
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return Err(InvalidEdit(format!("unsupported ui url \"{url}\"")).into());
+    }
+
+    let mut res = reqwest::Client::new()
+        .get(url)
+        .timeout(DOWNLOAD_TIMEOUT)
+        .send()
+        .await?;
+    if !res.status().is_success() {
+        bail!("failed to download ui from {url}: {}", res.status());
+    }
+
+    let too_large = || InvalidEdit(format!("ui bundle is larger than {MAX_UI_BUNDLE} bytes"));
+    if res
+        .content_length()
+        .map_or(false, |len| len > MAX_UI_BUNDLE)
+    {
+        return Err(too_large().into());
+    }
+    // content-length 可能缺失或不准确，按实际读取的长度再检查一次
+    let mut data = Vec::new();
+    while let Some(chunk) = res.chunk().await? {
+        if (data.len() + chunk.len()) as u64 > MAX_UI_BUNDLE {
+            return Err(too_large().into());
+        }
+        data.extend_from_slice(&chunk);
+    }
+    install_zip(name, data).await
+}
+
+/// 删除已安装的 ui
+pub fn remove_ui(name: &str) -> Result<()> {
+    let path = bundle_path(name)?;
+    if !path.exists() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("ui {name} not found")).into());
+    }
+    fs::remove_dir_all(path)?;
+    Ok(())
+}
+
+/// clash api 的 external_ui 目录，与核心一样以 sing_box_dir 为工作目录
+pub fn external_ui_path(external_ui: &str) -> PathBuf {
+    dirs::sing_box_dir().join(external_ui)
+}
+
+/// 启动核心前准备 external_ui 目录
+///
+/// clash_ui 是已安装的 ui 时复制到该目录，目录中没有 index.html 时给出警告。
+pub fn sync_external_ui() -> Result<()> {
+    let sing_box = ISingBox::read_file(&dirs::sing_box_path())?;
+    let external_ui = sing_box
+        .experimental
+        .and_then(|exp| exp.clash_api)
+        .and_then(|clash| clash.external_ui);
+    let clash_ui = Sword::global().config.read().clash_ui.clone();
+    let bundle = clash_ui.filter(|ui| has_ui(ui));
+
+    let path = match external_ui {
+        Some(external_ui) => external_ui_path(&external_ui),
+        None => {
+            if let Some(bundle) = bundle {
+                log::warn!(target: "app", "ui {bundle} is installed but clash api external_ui is not set");
+            }
+            return Ok(());
+        }
+    };
+
+    if let Some(bundle) = bundle {
+        populate_external_ui(&bundle, &bundle_path(&bundle)?, &path)?;
+    }
+    if !path.join("index.html").exists() {
+        fs::create_dir_all(&path)?;
+        log::warn!(target: "app", "external_ui {} has no index.html", path.display());
+    }
+    Ok(())
+}
+
+/// 复制已安装的 ui，重新安装后内容会跟着更新
+fn populate_external_ui(bundle: &str, source: &Path, path: &Path) -> Result<()> {
+    // 早期安装的 ui 没有哈希，重新安装之前不会更新
+    let id = fs::read_to_string(source.join(BUNDLE_ID)).unwrap_or_default();
+    let stamp = format!("{bundle}\n{id}");
+
+    let marker = path.join(EXTERNAL_UI_MARKER);
+    if path.exists() {
+        match fs::read_to_string(&marker) {
+            Ok(current) if current == stamp => return Ok(()),
+            Ok(_) => fs::remove_dir_all(path)?,
+            // 用户自己放置的 ui 不覆盖，空目录可以直接使用
+            Err(_) if fs::read_dir(path)?.next().is_none() => {}
+            Err(_) => {
+                log::warn!(target: "app", "external_ui {} is not managed by sword, left unchanged", path.display());
+                return Ok(());
+            }
+        }
+    }
+
+    copy_dir(source, path)?;
+    fs::write(marker, stamp)?;
+    log::info!(target: "app", "copied ui {bundle} to {}", path.display());
+    Ok(())
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        if entry.file_name() == BUNDLE_ID {
+            continue;
+        }
+        let target = to.join(entry.file_name());
+        match entry.file_type()?.is_dir() {
+            true => copy_dir(&entry.path(), &target)?,
+            false => {
+                fs::copy(entry.path(), target)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 解压到 dir，拒绝指向目录外的路径，以及文件过多或解压后过大的压缩包
+fn extract(data: &[u8], dir: &Path) -> Result<()> {
+    extract_with_limits(data, dir, MAX_UI_ENTRIES, MAX_UI_SIZE)
+}
+
+fn extract_with_limits(data: &[u8], dir: &Path, max_entries: usize, max_size: u64) -> Result<()> {
+    let mut archive = ZipArchive::new(Cursor::new(data))
+        .map_err(|err| InvalidEdit(format!("invalid ui bundle: {err}")))?;
+    if archive.len() > max_entries {
+        let message = format!("ui bundle has more than {max_entries} entries");
+        return Err(InvalidEdit(message).into());
+    }
+
+    // 按实际解压的长度计算，不相信压缩包中记录的大小
+    let mut remaining = max_size;
+
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index)?;
+        let path = match file.enclosed_name() {
+            Some(path) => dir.join(path),
+            None => {
+                let message = format!("invalid path {} in the ui bundle", file.name());
+                return Err(InvalidEdit(message).into());
+            }
+        };
+
+        if file.is_dir() {
+            fs::create_dir_all(&path)?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut out = fs::File::create(&path)?;
+        let written = io::copy(&mut (&mut file).take(remaining + 1), &mut out)?;
+        if written > remaining {
+            let message = format!("ui bundle is larger than {max_size} bytes when extracted");
+            return Err(InvalidEdit(message).into());
+        }
+        remaining -= written;
+    }
+    Ok(())
+}
+
+/// 解压后的根目录
+fn bundle_root(dir: &Path) -> Result<PathBuf> {
+    if dir.join("index.html").exists() {
+        return Ok(dir.to_path_buf());
+    }
+
+    let entries = fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| !p.file_name().map_or(false, |n| n == "__MACOSX"))
+        .collect::<Vec<_>>();
+    match entries.as_slice() {
+        [single] if single.is_dir() => Ok(single.clone()),
+        _ => Ok(dir.to_path_buf()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::temp_dir;
+    use std::io::Write;
+    use zip::{write::FileOptions, ZipWriter};
+
+    fn zip(files: &[&str]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        for name in files {
+            writer.start_file(*name, FileOptions::default()).unwrap();
+            writer.write_all(b"<html></html>").unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_dir() {
+        let dir = temp_dir("ui-slip");
+        let target = dir.join("bundle");
+        fs::create_dir_all(&target).unwrap();
+
+        let err = extract(&zip(&["index.html", "../evil.html"]), &target).unwrap_err();
+        assert!(err.is::<InvalidEdit>());
+        assert!(!dir.join("evil.html").exists());
+
+        let err = extract(&zip(&["/tmp/evil.html"]), &target).unwrap_err();
+        assert!(err.is::<InvalidEdit>());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn limits_entries_and_extracted_size() {
+        let dir = temp_dir("ui-limits");
+        let data = zip(&["index.html", "a.js", "b.js"]);
+
+        let err = extract_with_limits(&data, &dir.join("entries"), 2, 1024).unwrap_err();
+        assert!(err.is::<InvalidEdit>());
+
+        // 每个文件 13 字节
+        let err = extract_with_limits(&data, &dir.join("size"), 3, 38).unwrap_err();
+        assert!(err.is::<InvalidEdit>());
+
+        extract_with_limits(&data, &dir.join("ok"), 3, 39).unwrap();
+        assert!(dir.join("ok").join("b.js").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn uses_the_single_top_level_dir_as_root() {
+        let dir = temp_dir("ui-root");
+        let data = zip(&["yacd-gh-pages/index.html", "__MACOSX/._index.html"]);
+        extract(&data, &dir).unwrap();
+        assert_eq!(bundle_root(&dir).unwrap(), dir.join("yacd-gh-pages"));
+
+        let flat = dir.join("flat");
+        fs::create_dir_all(&flat).unwrap();
+        extract(&zip(&["index.html", "assets/app.js"]), &flat).unwrap();
+        assert_eq!(bundle_root(&flat).unwrap(), flat);
+
+        let mixed = dir.join("mixed");
+        fs::create_dir_all(&mixed).unwrap();
+        extract(&zip(&["a/index.html", "b/index.html"]), &mixed).unwrap();
+        assert_eq!(bundle_root(&mixed).unwrap(), mixed);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn updates_external_ui_when_reinstalled() {
+        let dir = temp_dir("ui-external");
+        let source = dir.join("yacd");
+        let path = dir.join("external");
+
+        let install = |data: &[u8]| {
+            let staging = dir.join(".yacd.tmp");
+            fs::create_dir_all(&staging).unwrap();
+            extract(data, &staging).unwrap();
+            fs::write(staging.join(BUNDLE_ID), bundle_id(data)).unwrap();
+            let _ = fs::remove_dir_all(&source);
+            fs::rename(&staging, &source).unwrap();
+        };
+
+        install(&zip(&["index.html"]));
+        populate_external_ui("yacd", &source, &path).unwrap();
+        assert!(path.join("index.html").exists());
+        assert!(!path.join(BUNDLE_ID).exists());
+
+        // 内容相同时不重新复制
+        fs::write(path.join("extra.js"), "").unwrap();
+        populate_external_ui("yacd", &source, &path).unwrap();
+        assert!(path.join("extra.js").exists());
+
+        install(&zip(&["index.html", "app.js"]));
+        populate_external_ui("yacd", &source, &path).unwrap();
+        assert!(path.join("app.js").exists());
+        assert!(!path.join("extra.js").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,403 @@
+use crate::{
+    config,
+    service::{ClashApi, IConnections},
+    utils::{self, dirs},
+};
+use anyhow::Result;
+use chrono::Local;
+use once_cell::sync::OnceCell;
+use parking_lot::{Mutex, RwLock};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+/// 采样 /connections 的间隔
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// 用量达到上限的比例时提醒
+const QUOTA_WARNING: u8 = 90;
+
+/// 按天统计的用量保留的天数
+const DAILY_RETENTION: usize = 400;
+
+/// 无法归属到具体出站的流量，例如两次采样之间已关闭的连接
+pub const UNATTRIBUTED: &str = "(other)";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct IUsage {
+    pub upload: u64,
+    pub download: u64,
+}
+
+impl IUsage {
+    pub fn total(&self) -> u64 {
+        self.upload + self.download
+    }
+
+    fn add(&mut self, other: IUsage) {
+        self.upload += other.upload;
+        self.download += other.download;
+    }
+}
+
+/// 以日期为键，按出站统计的用量
+pub type IUsageTable = BTreeMap<String, BTreeMap<String, IUsage>>;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct IUsageStore {
+    /// YYYY-MM-DD
+    #[serde(default)]
+    pub daily: IUsageTable,
+    /// YYYY-MM
+    #[serde(default)]
+    pub monthly: IUsageTable,
+    /// 每月已经提醒过的出站及比例，避免重复通知
+    #[serde(default)]
+    pub alerted: BTreeMap<String, BTreeMap<String, u8>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct IQuota {
+    pub outbound: String,
+    pub month: String,
+    pub limit: u64,
+    pub used: u64,
+}
+
+/// 上一次采样时的计数
+#[derive(Debug, Default)]
+struct Sample {
+    total: IUsage,
+    connections: HashMap<String, IUsage>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Usage {
+    pub store: Arc<RwLock<IUsageStore>>,
+
+    last: Arc<Mutex<Option<Sample>>>,
+}
+
+impl Usage {
+    pub fn global() -> &'static Usage {
+        static SERVICE: OnceCell<Usage> = OnceCell::new();
+        SERVICE.get_or_init(|| Usage {
+            store: Arc::new(RwLock::new(IUsageStore::default())),
+            last: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// 读取 usage.json
+    pub fn init_store(&self) -> Result<()> {
+        *self.store.write() = load_store(&dirs::usage_path())?;
+        Ok(())
+    }
+
+    fn save_store(&self) -> Result<()> {
+        let mut store = self.store.write();
+        prune(&mut store);
+        write_store(&dirs::usage_path(), &store)
+    }
+
+    /// 定时采样并记录用量
+    pub fn spawn_recorder() {
+        tauri::async_runtime::spawn(async move {
+            let usage = Usage::global();
+            // 读取失败时不记录，避免覆盖原有的数据
+            if let Err(err) = usage.init_store() {
+                log::error!(target: "app", "failed to load usage, recording disabled: {err}");
+                return;
+            }
+            loop {
+                tokio::time::sleep(SAMPLE_INTERVAL).await;
+
+                let connections = match ClashApi::current() {
+                    Ok(clash) => clash.connections().await,
+                    Err(err) => Err(err),
+                };
+                match connections {
+                    Ok(connections) => crate::log_err!(usage.record(&connections)),
+                    // 核心重启后计数会清零，重新开始计算增量
+                    Err(_) => *usage.last.lock() = None,
+                }
+            }
+        });
+    }
+
+    /// 计算与上一次采样的差值，按连接的出站累加
+    fn record(&self, connections: &IConnections) -> Result<()> {
+        let total = IUsage {
+            upload: connections.upload_total,
+            download: connections.download_total,
+        };
+        let current = connections
+            .connections
+            .iter()
+            .map(|c| {
+                let usage = IUsage {
+                    upload: c.upload,
+                    download: c.download,
+                };
+                (c.id.clone(), usage)
+            })
+            .collect::<HashMap<_, _>>();
+
+        let sample = Sample {
+            total,
+            connections: current.clone(),
+        };
+        let previous = match self.last.lock().replace(sample) {
+            Some(previous) => previous,
+            // 第一次采样只作为基准，此前的流量无法归属
+            None => return Ok(()),
+        };
+
+        // 总量变小说明核心已重启
+        let restarted =
+            total.upload < previous.total.upload || total.download < previous.total.download;
+        let total_delta = match restarted {
+            true => total,
+            false => delta(total, previous.total),
+        };
+        if total_delta.total() == 0 {
+            return Ok(());
+        }
+
+        let mut deltas: HashMap<String, IUsage> = HashMap::new();
+        let mut attributed = IUsage::default();
+        for conn in connections.connections.iter() {
+            let before = match restarted {
+                true => IUsage::default(),
+                false => previous
+                    .connections
+                    .get(&conn.id)
+                    .copied()
+                    .unwrap_or_default(),
+            };
+            let conn_delta = delta(current[&conn.id], before);
+            // chains 的第一个是实际连接的出站，其余是选择它的分组
+            let outbound = conn.chains.first().map_or(UNATTRIBUTED, |s| s.as_str());
+            deltas.entry(outbound.into()).or_default().add(conn_delta);
+            attributed.add(conn_delta);
+        }
+
+        let rest = delta(total_delta, attributed);
+        if rest.total() > 0 {
+            deltas.entry(UNATTRIBUTED.into()).or_default().add(rest);
+        }
+
+        let now = Local::now();
+        let day = now.format("%Y-%m-%d").to_string();
+        let month = now.format("%Y-%m").to_string();
+        {
+            let mut store = self.store.write();
+            for (outbound, usage) in deltas {
+                store
+                    .daily
+                    .entry(day.clone())
+                    .or_default()
+                    .entry(outbound.clone())
+                    .or_default()
+                    .add(usage);
+                store
+                    .monthly
+                    .entry(month.clone())
+                    .or_default()
+                    .entry(outbound)
+                    .or_default()
+                    .add(usage);
+            }
+        }
+
+        self.check_quota(&month);
+        self.save_store()
+    }
+
+    /// 本月各出站的用量与上限
+    pub fn quota(&self) -> Vec<IQuota> {
+        let month = Local::now().format("%Y-%m").to_string();
+        self.quota_of(&month)
+    }
+
+    fn quota_of(&self, month: &str) -> Vec<IQuota> {
+        let limits = config::Sword::global().config.read().usage_quota.clone();
+        let store = self.store.read();
+        let used = store.monthly.get(month);
+
+        limits
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(outbound, limit)| IQuota {
+                used: used.and_then(|m| m.get(&outbound)).map_or(0, |u| u.total()),
+                month: month.into(),
+                outbound,
+                limit,
+            })
+            .collect()
+    }
+
+    /// 用量接近或超过上限时发送通知，每个阶段每月只提醒一次
+    fn check_quota(&self, month: &str) {
+        for quota in self.quota_of(month) {
+            if quota.limit == 0 {
+                continue;
+            }
+            let percent = (quota.used.saturating_mul(100) / quota.limit).min(100) as u8;
+            let level = match percent {
+                p if p >= 100 => 100,
+                p if p >= QUOTA_WARNING => QUOTA_WARNING,
+                _ => continue,
+            };
+
+            let mut store = self.store.write();
+            let alerted = store
+                .alerted
+                .entry(month.into())
+                .or_default()
+                .entry(quota.outbound.clone())
+                .or_default();
+            if *alerted >= level {
+                continue;
+            }
+            *alerted = level;
+            drop(store);
+
+            let body = format!(
+                "{} has used {}% of its monthly quota ({} / {} MB)",
+                quota.outbound,
+                percent,
+                quota.used / 1024 / 1024,
+                quota.limit / 1024 / 1024
+            );
+            let _ = tauri::api::notification::Notification::new(utils::IDENTIFIER)
+                .title("Traffic Quota")
+                .body(body)
+                .show();
+        }
+    }
+}
+
+/// 读取用量文件，内容损坏时移到一旁并从空白开始
+fn load_store(path: &Path) -> Result<IUsageStore> {
+    if !path.exists() {
+        return Ok(IUsageStore::default());
+    }
+
+    let content = fs::read_to_string(path)?;
+    match serde_json::from_str(&content) {
+        Ok(store) => Ok(store),
+        Err(err) => {
+            let time = Local::now().format("%Y%m%d%H%M%S");
+            let backup = path.with_extension(format!("json.corrupt-{time}"));
+            fs::rename(path, &backup)?;
+            log::warn!(target: "app", "invalid usage file moved to {}: {err}", backup.display());
+            Ok(IUsageStore::default())
+        }
+    }
+}
+
+/// 先写入临时文件再替换，写入中断时不会损坏原文件
+fn write_store(path: &Path, store: &IUsageStore) -> Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string(store)?.as_bytes())?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+/// 只保留最近的按天统计和提醒记录，按月的用量很小全部保留
+fn prune(store: &mut IUsageStore) {
+    keep_last(&mut store.daily, DAILY_RETENTION);
+    keep_last(&mut store.alerted, 2);
+}
+
+fn keep_last<V>(map: &mut BTreeMap<String, V>, count: usize) {
+    let excess = map.len().saturating_sub(count);
+    if excess == 0 {
+        return;
+    }
+    if let Some(first) = map.keys().nth(excess).cloned() {
+        *map = map.split_off(&first);
+    }
+}
+
+fn delta(current: IUsage, previous: IUsage) -> IUsage {
+    IUsage {
+        upload: current.upload.saturating_sub(previous.upload),
+        download: current.download.saturating_sub(previous.download),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::temp_dir;
+
+    fn usage(total: u64) -> BTreeMap<String, IUsage> {
+        let usage = IUsage {
+            upload: total,
+            download: 0,
+        };
+        BTreeMap::from([("proxy".to_string(), usage)])
+    }
+
+    #[test]
+    fn saves_and_loads_store() {
+        let dir = temp_dir("usage");
+        let path = dir.join("usage.json");
+        assert!(load_store(&path).unwrap().daily.is_empty());
+
+        let mut store = IUsageStore::default();
+        store.daily.insert("2026-10-18".into(), usage(10));
+        write_store(&path, &store).unwrap();
+
+        let loaded = load_store(&path).unwrap();
+        assert_eq!(loaded.daily["2026-10-18"]["proxy"].total(), 10);
+        assert!(!path.with_extension("json.tmp").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn moves_corrupt_store_aside() {
+        let dir = temp_dir("usage-corrupt");
+        let path = dir.join("usage.json");
+        fs::write(&path, "{\"daily\": {\"2026-10-18\": ").unwrap();
+
+        assert!(load_store(&path).unwrap().daily.is_empty());
+        assert!(!path.exists());
+        let backups = fs::read_dir(&dir)
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_string_lossy().contains("corrupt"))
+            .count();
+        assert_eq!(backups, 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn prunes_old_entries() {
+        let mut store = IUsageStore::default();
+        for day in 0..DAILY_RETENTION + 10 {
+            store.daily.insert(format!("day-{day:04}"), usage(1));
+        }
+        for month in ["2026-08", "2026-09", "2026-10"] {
+            store.monthly.insert(month.into(), usage(1));
+            store.alerted.insert(month.into(), BTreeMap::new());
+        }
+
+        prune(&mut store);
+        assert_eq!(store.daily.len(), DAILY_RETENTION);
+        assert!(store.daily.contains_key("day-0409"));
+        assert!(!store.daily.contains_key("day-0009"));
+        assert_eq!(store.monthly.len(), 3);
+        assert_eq!(
+            store.alerted.keys().collect::<Vec<_>>(),
+            ["2026-09", "2026-10"]
+        );
+    }
+}
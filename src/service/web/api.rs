@@ -0,0 +1,1167 @@
+use super::{
+    audit,
+    auth::{optional_access, with_access, with_auth, with_master, with_stream_auth, Forbidden},
+    dto::{self, ICapabilitiesDTO, ISwordDTO},
+    error::{error_details, error_reply},
+    openapi,
+};
+use crate::{
+    config::{self, TokenScope},
+    service::{self, ClashApi},
+    utils::{
+        events::{self, SwordEvent},
+        init,
+    },
+};
+use futures::{future, SinkExt};
+use percent_encoding::percent_decode_str;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream},
+    StreamExt,
+};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use warp::{
+    filters::{path::Tail, BoxedFilter},
+    http::HeaderMap,
+    hyper::{body::Bytes, Body, Method, StatusCode},
+    reply::Response,
+    sse::Event,
+    ws::{Message, WebSocket, Ws},
+    Filter, Rejection, Reply,
+};
+
+/// 接口的根路径，不带版本的 /api 是当前版本 /api/v1 的别名
+fn api() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::path("api").and(warp::path(dto::API_VERSION).or(warp::any()).unify())
+}
+
+/// 读取 clash api 只需要只读权限，其他请求需要控制核心的权限
+fn with_clash_auth() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::method()
+        .and(with_access(TokenScope::Read))
+        .and_then(|method: Method, access: config::Access| async move {
+            match method == Method::GET || access.allows(TokenScope::CoreControl) {
+                true => Ok(()),
+                false => Err(warp::reject::custom(Forbidden)),
+            }
+        })
+        .untuple_one()
+}
+
+/// websocket 请求交给 clash_ws 处理，避免重复鉴权
+fn without_upgrade() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("upgrade")
+        .and_then(|upgrade: Option<String>| async move {
+            match upgrade {
+                Some(_) => Err(warp::reject()),
+                None => Ok(()),
+            }
+        })
+        .untuple_one()
+}
+
+/// 原始的 query 字符串，没有参数时为空
+fn raw_query() -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+    warp::query::raw().or_else(|_| async { Ok::<_, Rejection>((String::new(),)) })
+}
+
+/// 去掉 sword 自己的 token 参数，剩下的原样转发，保留重复的参数和顺序
+fn forward_query(query: &str) -> String {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .filter(|pair| pair.split('=').next() != Some("token"))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// 逐跳的请求头，只在一段连接内有效，不能转发
+const HOP_BY_HOP: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// 需要转发的端到端头，skip 中的由代理自己设置
+fn end_to_end(headers: &HeaderMap, skip: &[&str]) -> HeaderMap {
+    // Connection 中列出的头同样只属于当前连接
+    let listed = headers
+        .get_all("connection")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .collect::<Vec<_>>();
+
+    headers
+        .iter()
+        .filter(|(name, _)| {
+            let name = name.as_str();
+            !HOP_BY_HOP.contains(&name)
+                && !skip.contains(&name)
+                && !listed.iter().any(|l| l == name)
+        })
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect()
+}
+
+/// 隐藏敏感字段
+fn redacted<T: Serialize>(value: &T) -> anyhow::Result<Value> {
+    let mut value = serde_json::to_value(value)?;
+    config::redact(&mut value);
+    Ok(value)
+}
+
+/// 返回隐藏了敏感字段的json，etag 按原始内容计算
+fn json_with_etag<T: Serialize>(value: &T) -> Response {
+    json_with_etag_of(value, value)
+}
+
+/// 返回 body，etag 按保存的配置计算，便于 PUT 时校验 If-Match
+fn json_with_etag_of<T: Serialize, U: Serialize>(value: &T, body: &U) -> Response {
+    let result = config::etag(value).and_then(|etag| Ok((etag, redacted(body)?)));
+    match result {
+        Ok((etag, value)) => {
+            warp::reply::with_header(warp::reply::json(&value), "etag", etag).into_response()
+        }
+        Err(err) => error_response(err),
+    }
+}
+
+/// 写入结果转换为响应，并带上新的 etag
+fn put_result(result: anyhow::Result<String>) -> Response {
+    match result {
+        Ok(etag) => warp::reply::with_header(StatusCode::NO_CONTENT, "etag", etag).into_response(),
+        Err(err) => error_response(err),
+    }
+}
+
+fn empty_result(result: anyhow::Result<()>) -> Response {
+    match result {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(err) => error_response(err),
+    }
+}
+
+/// etag 不匹配时返回 412，修改不合法时返回 422
+fn error_response(err: anyhow::Error) -> Response {
+    if err.is::<config::PreconditionFailed>() {
+        return error_reply(
+            StatusCode::PRECONDITION_FAILED,
+            "precondition_failed",
+            err.to_string(),
+        );
+    }
+    if err.is::<config::InvalidEdit>() {
+        log::warn!(target: "app", "{err}");
+        return error_reply(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "invalid_edit",
+            err.to_string(),
+        );
+    }
+    let not_found = err
+        .downcast_ref::<std::io::Error>()
+        .map_or(false, |e| e.kind() == std::io::ErrorKind::NotFound);
+    if not_found {
+        return error_reply(StatusCode::NOT_FOUND, "not_found", err.to_string());
+    }
+
+    log::error!(target: "app", "{err}");
+    error_reply(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "internal",
+        err.to_string(),
+    )
+}
+
+/// 核心未运行或 clash api 出错时返回 502
+fn clash_error(err: anyhow::Error) -> Response {
+    log::warn!(target: "app", "clash api: {err}");
+    error_reply(StatusCode::BAD_GATEWAY, "core_unavailable", err.to_string())
+}
+
+fn decode(segment: &str) -> String {
+    percent_decode_str(segment).decode_utf8_lossy().into_owned()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub(super) struct IVersionDTO {
+    pub version: String,
+}
+
+/// GET /api/v1/version
+pub fn get_version() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("version"))
+        .and(warp::get())
+        .and(with_auth(TokenScope::Read))
+        .map(|| {
+            warp::reply::json(&IVersionDTO {
+                version: init::app_version(),
+            })
+        })
+        .boxed()
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub(super) struct IHealthDTO {
+    pub status: &'static str,
+    /// 全部地址都已成功监听
+    pub listening: bool,
+    /// clash api 是否可以访问
+    pub core: bool,
+    /// 监听地址、错误和证书指纹，只返回给已鉴权的请求
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub web: Option<service::IWebStatus>,
+}
+
+/// GET /api/v1/health
+///
+/// 不需要鉴权，供外部的监控使用
+pub fn get_health() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("health"))
+        .and(warp::get())
+        .and(optional_access())
+        .and_then(|access: Option<config::Access>| async move {
+            let web = service::Web::global().status.read().clone();
+            let core = match ClashApi::current() {
+                Ok(clash) => clash.version().await.is_ok(),
+                Err(_) => false,
+            };
+            let listening = web.errors.is_empty();
+            let status = match listening && core {
+                true => "ok",
+                false => "degraded",
+            };
+            Ok::<_, Rejection>(warp::reply::json(&IHealthDTO {
+                status,
+                listening,
+                core,
+                web: access.map(|_| web),
+            }))
+        })
+        .boxed()
+}
+
+/// GET /api/v1/capabilities
+pub fn get_capabilities() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("capabilities"))
+        .and(warp::get())
+        .and(with_access(TokenScope::Read))
+        .map(|access: config::Access| {
+            let scopes = [
+                TokenScope::Read,
+                TokenScope::ConfigWrite,
+                TokenScope::CoreControl,
+            ];
+            let mut scopes = scopes
+                .into_iter()
+                .filter(|s| access.allows(*s))
+                .map(|s| s.name().to_string())
+                .collect::<Vec<_>>();
+            // 只有 web_secret 可以管理 token 和 ui
+            if access.is_master() {
+                scopes.push("master".into());
+            }
+            warp::reply::json(&ICapabilitiesDTO {
+                api_version: dto::API_VERSION.into(),
+                versions: vec![dto::API_VERSION.into()],
+                app_version: init::app_version(),
+                features: dto::FEATURES.iter().map(|f| f.to_string()).collect(),
+                scopes,
+            })
+        })
+        .boxed()
+}
+
+/// GET /api/v1/openapi.json
+///
+/// 不需要鉴权，接口的描述不包含配置内容
+pub fn get_openapi() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("openapi.json"))
+        .and(warp::get())
+        .map(|| warp::reply::json(openapi::document()))
+        .boxed()
+}
+
+/// GET /api/v1/config
+pub fn get_config() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("config"))
+        .and(warp::get())
+        .and(with_auth(TokenScope::Read))
+        .map(|| {
+            let config = config::Sword::global().config.read();
+            json_with_etag_of(&*config, &ISwordDTO::from(&*config))
+        })
+        .boxed()
+}
+
+/// PUT /api/v1/config
+pub fn put_config() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("config"))
+        .and(warp::put())
+        .and(with_access(TokenScope::ConfigWrite))
+        .and(warp::header::optional::<String>("if-match"))
+        .and(warp::body::json())
+        .map(
+            |access: config::Access, if_match: Option<String>, mut value: ISwordDTO| {
+                audit::track(|| {
+                    let sword = config::Sword::global();
+                    let current = sword.config.read().clone();
+                    if !access.is_master() {
+                        value.keep_protected(&current);
+                    }
+                    put_result(sword.set_config(value.apply(&current), if_match.as_deref()))
+                })
+            },
+        )
+        .boxed()
+}
+
+/// GET /api/v1/sing_box
+pub fn get_sing_box() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("sing_box"))
+        .and(warp::get())
+        .and(with_auth(TokenScope::Read))
+        .map(|| {
+            let config = config::Sword::global().sing_box.read();
+            json_with_etag(&*config)
+        })
+        .boxed()
+}
+
+/// PUT /api/v1/sing_box
+pub fn put_sing_box() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("sing_box"))
+        .and(warp::put())
+        .and(with_auth(TokenScope::ConfigWrite))
+        .and(warp::header::optional::<String>("if-match"))
+        .and(warp::body::json())
+        .map(|if_match: Option<String>, value: config::ISingBox| {
+            let sword = config::Sword::global();
+            audit::track(|| put_result(sword.set_sing_box(value, if_match.as_deref())))
+        })
+        .boxed()
+}
+
+/// PATCH /api/v1/sing_box
+///
+/// `application/json-patch+json` 按 RFC 6902 处理，其余按 RFC 7396 merge patch 处理
+pub fn patch_sing_box() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("sing_box"))
+        .and(warp::patch())
+        .and(with_auth(TokenScope::ConfigWrite))
+        .and(warp::header::optional::<String>("content-type"))
+        .and(warp::header::optional::<String>("if-match"))
+        .and(warp::body::bytes())
+        .map(
+            |content_type: Option<String>, if_match: Option<String>, body: Bytes| {
+                let patch: Value = match serde_json::from_slice(&body) {
+                    Ok(patch) => patch,
+                    Err(err) => {
+                        return error_reply(
+                            StatusCode::BAD_REQUEST,
+                            "invalid_json",
+                            err.to_string(),
+                        )
+                    }
+                };
+                let is_json_patch = content_type
+                    .map(|ct| ct.starts_with("application/json-patch+json"))
+                    .unwrap_or(false);
+
+                let sword = config::Sword::global();
+                audit::track(|| {
+                    put_result(sword.edit_sing_box(
+                        if_match.as_deref(),
+                        |doc| match is_json_patch {
+                            true => config::json_patch(doc, patch),
+                            false => config::merge_patch(doc, &patch),
+                        },
+                    ))
+                })
+            },
+        )
+        .boxed()
+}
+
+/// 将路径的剩余部分转换为 json pointer
+///
+/// 剩余部分为空时不匹配，避免整个配置的请求落到这里写入 key 为空的字段。
+fn json_pointer() -> impl Filter<Extract = (String,), Error = Rejection> + Copy {
+    warp::path::tail().and_then(|tail: Tail| async move {
+        if tail.as_str().is_empty() {
+            return Err(warp::reject::not_found());
+        }
+        let tail = percent_decode_str(tail.as_str())
+            .decode_utf8()
+            .map_err(|_| warp::reject::not_found())?;
+        Ok::<_, Rejection>(format!("/{tail}"))
+    })
+}
+
+/// GET /api/v1/sing_box/{json-pointer}
+pub fn get_sing_box_pointer() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path("sing_box"))
+        .and(json_pointer())
+        .and(warp::get())
+        .and(with_auth(TokenScope::Read))
+        .map(|pointer: String| {
+            let config = config::Sword::global().sing_box.read();
+            let result = config::etag(&*config).and_then(|etag| {
+                let value = redacted(&*config)?;
+                Ok((etag, value.pointer(&pointer).cloned()))
+            });
+
+            match result {
+                // etag 对应整个配置，便于后续的 PUT 带上 If-Match
+                Ok((etag, Some(value))) => {
+                    warp::reply::with_header(warp::reply::json(&value), "etag", etag)
+                        .into_response()
+                }
+                Ok((_, None)) => {
+                    let message = format!("nothing at {pointer}");
+                    error_reply(StatusCode::NOT_FOUND, "not_found", message)
+                }
+                Err(err) => error_response(err),
+            }
+        })
+        .boxed()
+}
+
+/// PUT /api/v1/sing_box/{json-pointer}
+pub fn put_sing_box_pointer() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path("sing_box"))
+        .and(json_pointer())
+        .and(warp::put())
+        .and(with_auth(TokenScope::ConfigWrite))
+        .and(warp::header::optional::<String>("if-match"))
+        .and(warp::body::json())
+        .map(|pointer: String, if_match: Option<String>, value: Value| {
+            let sword = config::Sword::global();
+            audit::track(|| {
+                put_result(sword.edit_sing_box(if_match.as_deref(), |doc| {
+                    config::pointer_set(doc, &pointer, value)
+                }))
+            })
+        })
+        .boxed()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub(super) struct IMissingDTO {
+    pub name: String,
+    pub pointer: String,
+}
+
+/// GET /api/v1/render
+///
+/// 预览填充模板变量后交给核心的配置，缺少变量时返回 422，details 中为缺少的变量
+pub fn get_render() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("render"))
+        .and(warp::get())
+        .and(with_auth(TokenScope::Read))
+        .map(|| match config::Sword::global().render_sing_box(false) {
+            Ok(config) => match redacted(&config) {
+                Ok(config) => warp::reply::json(&config).into_response(),
+                Err(err) => error_response(err),
+            },
+            Err(err) => match err.downcast_ref::<config::MissingVariables>() {
+                Some(missing) => {
+                    let list = missing
+                        .0
+                        .iter()
+                        .map(|m| IMissingDTO {
+                            name: m.name.clone(),
+                            pointer: m.pointer.clone(),
+                        })
+                        .collect::<Vec<_>>();
+                    error_details(
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        "missing_variables",
+                        err.to_string(),
+                        serde_json::to_value(list).ok(),
+                    )
+                }
+                None => error_response(err),
+            },
+        })
+        .boxed()
+}
+
+/// GET /api/v1/effective
+///
+/// 各层合并后的配置，以及每个字段来自哪一层
+pub fn get_effective() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("effective"))
+        .and(warp::get())
+        .and(with_auth(TokenScope::Read))
+        .map(|| match config::Sword::global().compose_sing_box() {
+            Ok(mut composed) => {
+                config::redact(&mut composed.config);
+                warp::reply::json(&composed).into_response()
+            }
+            Err(err) => error_response(err),
+        })
+        .boxed()
+}
+
+/// GET /api/v1/layers/{name}
+pub fn get_layer() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("layers" / String))
+        .and(warp::get())
+        .and(with_auth(TokenScope::Read))
+        .map(|name: String| match config::read_layer(&name) {
+            Ok(mut value) => {
+                config::redact(&mut value);
+                warp::reply::json(&value).into_response()
+            }
+            Err(err) => error_response(err),
+        })
+        .boxed()
+}
+
+/// PUT /api/v1/layers/{name}
+pub fn put_layer() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("layers" / String))
+        .and(warp::put())
+        .and(with_auth(TokenScope::ConfigWrite))
+        .and(warp::body::json())
+        .map(|name: String, value: Value| {
+            audit::track(|| empty_result(config::Sword::global().put_layer(&name, &value)))
+        })
+        .boxed()
+}
+
+/// DELETE /api/v1/layers/{name}
+pub fn delete_layer() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("layers" / String))
+        .and(warp::delete())
+        .and(with_auth(TokenScope::ConfigWrite))
+        .map(|name: String| {
+            audit::track(|| empty_result(config::Sword::global().delete_layer(&name)))
+        })
+        .boxed()
+}
+
+/// GET /api/v1/secrets
+///
+/// 只返回名字，密文不会通过接口返回
+pub fn get_secrets() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("secrets"))
+        .and(warp::get())
+        .and(with_auth(TokenScope::Read))
+        .map(|| match config::list_secrets() {
+            Ok(names) => warp::reply::json(&names).into_response(),
+            Err(err) => error_response(err),
+        })
+        .boxed()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub(super) struct ISecretDTO {
+    pub value: String,
+}
+
+/// PUT /api/v1/secrets/{name}
+pub fn put_secret() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("secrets" / String))
+        .and(warp::put())
+        .and(with_auth(TokenScope::ConfigWrite))
+        .and(warp::body::json())
+        .map(|name: String, body: ISecretDTO| {
+            audit::track(|| empty_result(config::Sword::global().set_secret(&name, &body.value)))
+        })
+        .boxed()
+}
+
+/// DELETE /api/v1/secrets/{name}
+pub fn delete_secret() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("secrets" / String))
+        .and(warp::delete())
+        .and(with_auth(TokenScope::ConfigWrite))
+        .map(|name: String| {
+            audit::track(|| empty_result(config::Sword::global().remove_secret(&name)))
+        })
+        .boxed()
+}
+
+/// GET /api/v1/proxies
+pub fn get_proxies() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("proxies"))
+        .and(warp::get())
+        .and(with_auth(TokenScope::Read))
+        .and_then(|| async move {
+            let result = match ClashApi::current() {
+                Ok(clash) => clash.proxies().await,
+                Err(err) => Err(err),
+            };
+            Ok::<_, Rejection>(match result {
+                Ok(proxies) => warp::reply::json(&proxies).into_response(),
+                Err(err) => clash_error(err),
+            })
+        })
+        .boxed()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub(super) struct ISelectDTO {
+    pub name: String,
+}
+
+/// PUT /api/v1/proxies/{group}
+pub fn put_proxy() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("proxies" / String))
+        .and(warp::put())
+        .and(with_auth(TokenScope::CoreControl))
+        .and(warp::body::json())
+        .and_then(|group: String, body: ISelectDTO| async move {
+            let result = match ClashApi::current() {
+                Ok(clash) => clash.select(&decode(&group), &body.name).await,
+                Err(err) => Err(err),
+            };
+            Ok::<_, Rejection>(match result {
+                Ok(_) => StatusCode::NO_CONTENT.into_response(),
+                Err(err) => clash_error(err),
+            })
+        })
+        .boxed()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub(super) struct IModeDTO {
+    pub mode: String,
+    /// 同时写入配置的 default_mode
+    #[serde(default)]
+    pub persist: bool,
+}
+
+/// PUT /api/v1/mode
+pub fn put_mode() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("mode"))
+        .and(warp::put())
+        .and(with_auth(TokenScope::CoreControl))
+        .and(warp::body::json())
+        .and_then(|body: IModeDTO| async move {
+            let mode = match service::parse_mode(&body.mode) {
+                Some(mode) => mode,
+                None => {
+                    let err = config::InvalidEdit(format!("unknown mode `{}`", body.mode));
+                    return Ok::<_, Rejection>(error_response(err.into()));
+                }
+            };
+
+            let result = match ClashApi::current() {
+                Ok(clash) => clash.set_mode(mode).await,
+                Err(err) => Err(err),
+            };
+            if let Err(err) = result {
+                return Ok(clash_error(err));
+            }
+
+            Ok(match body.persist {
+                true => audit::track(|| put_result(config::Sword::global().set_default_mode(mode))),
+                false => StatusCode::NO_CONTENT.into_response(),
+            })
+        })
+        .boxed()
+}
+
+/// GET /api/v1/outbounds/latency
+pub fn get_latency() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("outbounds" / "latency"))
+        .and(warp::get())
+        .and(with_auth(TokenScope::Read))
+        .map(|| {
+            let results = service::Latency::global().results.read().clone();
+            warp::reply::json(&results)
+        })
+        .boxed()
+}
+
+/// POST /api/v1/outbounds/latency
+pub fn test_latency() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("outbounds" / "latency"))
+        .and(warp::post())
+        .and(with_auth(TokenScope::CoreControl))
+        .and_then(|| async move {
+            Ok::<_, Rejection>(match service::Latency::global().test_all().await {
+                Ok(results) => warp::reply::json(&results).into_response(),
+                Err(err) => error_response(err),
+            })
+        })
+        .boxed()
+}
+
+/// GET /api/v1/stats/traffic
+///
+/// 以 sse 的形式推送每秒的上传下载速率
+pub fn get_traffic() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("stats" / "traffic"))
+        .and(warp::get())
+        .and(with_stream_auth(TokenScope::Read))
+        .map(|| {
+            let receiver = service::Traffic::global().subscribe();
+            // 跟不上推送速度时丢弃积压的数据
+            let stream = BroadcastStream::new(receiver)
+                .filter_map(|traffic| traffic.ok())
+                .map(|traffic| Event::default().json_data(traffic));
+            warp::sse::reply(warp::sse::keep_alive().stream(stream))
+        })
+        .boxed()
+}
+
+/// GET /api/v1/events
+///
+/// 以 sse 的形式推送配置和核心的状态变化，事件名与 json 中的 type 相同
+pub fn get_events() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("events"))
+        .and(warp::get())
+        .and(with_stream_auth(TokenScope::Read))
+        .map(|| {
+            // 跟不上推送速度时通知客户端重新读取，而不是悄悄丢弃事件
+            let stream = BroadcastStream::new(events::subscribe())
+                .map(|event| match event {
+                    Ok(event) => event,
+                    Err(BroadcastStreamRecvError::Lagged(missed)) => SwordEvent::Resync { missed },
+                })
+                .map(|event| Event::default().event(event.name()).json_data(&event));
+            warp::sse::reply(warp::sse::keep_alive().stream(stream))
+        })
+        .boxed()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IUsageQuery {
+    /// 起止日期，按字符串比较，可以是 YYYY-MM 或 YYYY-MM-DD
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+/// GET /api/v1/stats/usage/{daily|monthly}
+pub fn get_usage() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("stats" / "usage" / String))
+        .and(warp::get())
+        .and(with_auth(TokenScope::Read))
+        .and(warp::query::<IUsageQuery>())
+        .map(|period: String, query: IUsageQuery| {
+            let store = service::Usage::global().store.read();
+            let table = match period.as_str() {
+                "daily" => &store.daily,
+                "monthly" => &store.monthly,
+                _ => {
+                    let message = format!("unknown period `{period}`");
+                    return error_reply(StatusCode::NOT_FOUND, "not_found", message);
+                }
+            };
+            let table = table
+                .iter()
+                .filter(|(date, _)| query.from.as_ref().map_or(true, |f| *date >= f))
+                .filter(|(date, _)| query.to.as_ref().map_or(true, |t| *date <= t))
+                .collect::<BTreeMap<_, _>>();
+            warp::reply::json(&table).into_response()
+        })
+        .boxed()
+}
+
+/// GET /api/v1/stats/quota
+pub fn get_quota() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("stats" / "quota"))
+        .and(warp::get())
+        .and(with_auth(TokenScope::Read))
+        .map(|| warp::reply::json(&service::Usage::global().quota()))
+        .boxed()
+}
+
+/// GET /api/v1/connections
+pub fn get_connections() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("connections"))
+        .and(warp::get())
+        .and(with_auth(TokenScope::Read))
+        .and_then(|| async move {
+            let result = match ClashApi::current() {
+                Ok(clash) => clash.connections().await,
+                Err(err) => Err(err),
+            };
+            Ok::<_, Rejection>(match result {
+                Ok(connections) => warp::reply::json(&connections).into_response(),
+                Err(err) => clash_error(err),
+            })
+        })
+        .boxed()
+}
+
+/// DELETE /api/v1/connections
+pub fn delete_connections() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("connections"))
+        .and(warp::delete())
+        .and(with_auth(TokenScope::CoreControl))
+        .and_then(|| async move {
+            let result = match ClashApi::current() {
+                Ok(clash) => clash.close_connections().await,
+                Err(err) => Err(err),
+            };
+            Ok::<_, Rejection>(match result {
+                Ok(_) => StatusCode::NO_CONTENT.into_response(),
+                Err(err) => clash_error(err),
+            })
+        })
+        .boxed()
+}
+
+/// DELETE /api/v1/connections/{id}
+pub fn delete_connection() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("connections" / String))
+        .and(warp::delete())
+        .and(with_auth(TokenScope::CoreControl))
+        .and_then(|id: String| async move {
+            let result = match ClashApi::current() {
+                Ok(clash) => clash.close_connection(&decode(&id)).await,
+                Err(err) => Err(err),
+            };
+            Ok::<_, Rejection>(match result {
+                Ok(_) => StatusCode::NO_CONTENT.into_response(),
+                Err(err) => clash_error(err),
+            })
+        })
+        .boxed()
+}
+
+/// /clash/* 的 websocket，用于 /traffic、/logs 和 /connections
+pub fn clash_ws() -> BoxedFilter<(impl warp::Reply,)> {
+    warp::path("clash")
+        .and(warp::path::tail())
+        .and(warp::ws())
+        .and(with_stream_auth(TokenScope::Read))
+        .and(raw_query())
+        .and_then(|tail: Tail, ws: Ws, query: String| async move {
+            let clash = match ClashApi::current() {
+                Ok(clash) => clash,
+                Err(err) => return Ok::<_, Rejection>(clash_error(err)),
+            };
+            let url = clash.ws_url(&format!("/{}", tail.as_str()), &forward_query(&query));
+            Ok(ws
+                .on_upgrade(move |socket| proxy_ws(socket, url))
+                .into_response())
+        })
+        .boxed()
+}
+
+async fn proxy_ws(socket: WebSocket, url: String) {
+    let upstream = match tokio_tungstenite::connect_async(&url).await {
+        Ok((upstream, _)) => upstream,
+        Err(err) => {
+            log::warn!(target: "app", "clash api websocket: {err}");
+            return;
+        }
+    };
+
+    let (mut client_tx, mut client_rx) = futures::StreamExt::split(socket);
+    let (mut upstream_tx, mut upstream_rx) = futures::StreamExt::split(upstream);
+
+    let to_upstream = async move {
+        while let Some(Ok(msg)) = client_rx.next().await {
+            let msg = if msg.is_text() {
+                WsMessage::Text(msg.to_str().unwrap_or_default().into())
+            } else if msg.is_binary() {
+                WsMessage::Binary(msg.into_bytes())
+            } else if msg.is_close() {
+                break;
+            } else {
+                continue;
+            };
+            if upstream_tx.send(msg).await.is_err() {
+                break;
+            }
+        }
+        let _ = upstream_tx.close().await;
+    };
+
+    let to_client = async move {
+        while let Some(Ok(msg)) = upstream_rx.next().await {
+            let msg = match msg {
+                WsMessage::Text(text) => Message::text(text),
+                WsMessage::Binary(data) => Message::binary(data),
+                WsMessage::Close(_) => break,
+                _ => continue,
+            };
+            if client_tx.send(msg).await.is_err() {
+                break;
+            }
+        }
+        let _ = client_tx.close().await;
+    };
+
+    future::select(Box::pin(to_upstream), Box::pin(to_client)).await;
+}
+
+/// /clash/* 转发到 clash api，并替换为 clash 的密钥
+pub fn clash_proxy() -> BoxedFilter<(impl warp::Reply,)> {
+    warp::path("clash")
+        .and(warp::path::tail())
+        .and(without_upgrade())
+        .and(with_clash_auth())
+        .and(warp::method())
+        .and(raw_query())
+        .and(warp::header::headers_cloned())
+        .and(warp::body::bytes())
+        .and_then(
+            |tail: Tail, method: Method, query: String, headers: HeaderMap, body: Bytes| async move {
+                let path = format!("/{}", tail.as_str());
+                let query = forward_query(&query);
+                // 鉴权换成 clash 的密钥，长度和主机由转发的请求重新设置
+                let headers = end_to_end(&headers, &["authorization", "host", "content-length"]);
+                let result = match ClashApi::current() {
+                    Ok(clash) => {
+                        clash
+                            .forward(method, &path, &query, headers, body.to_vec())
+                            .await
+                    }
+                    Err(err) => Err(err),
+                };
+                let upstream = match result {
+                    Ok(upstream) => upstream,
+                    Err(err) => return Ok::<_, Rejection>(clash_error(err)),
+                };
+
+                let mut builder = warp::http::Response::builder().status(upstream.status());
+                if let Some(headers) = builder.headers_mut() {
+                    headers.extend(end_to_end(upstream.headers(), &[]));
+                }
+                // 流式转发，/traffic 等接口的响应不会结束
+                let body = Body::wrap_stream(upstream.bytes_stream());
+                Ok(builder
+                    .body(body)
+                    .unwrap_or_else(|err| clash_error(err.into())))
+            },
+        )
+        .boxed()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub(super) struct ITokenDTO {
+    pub name: String,
+    pub scopes: Vec<TokenScope>,
+    pub created_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+    pub expired: bool,
+}
+
+/// GET /api/v1/ui
+pub fn get_ui() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("ui"))
+        .and(warp::get())
+        .and(with_auth(TokenScope::Read))
+        .map(|| match service::list_ui() {
+            Ok(list) => warp::reply::json(&list).into_response(),
+            Err(err) => error_response(err),
+        })
+        .boxed()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub(super) struct IInstallUiDTO {
+    /// zip 压缩包的下载地址
+    pub url: String,
+}
+
+/// PUT /api/v1/ui/{name}
+///
+/// 请求体为 zip 压缩包，或者 json 格式的下载地址。ui 与接口同源，只允许 web_secret 安装
+pub fn put_ui() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("ui" / String))
+        .and(warp::put())
+        .and(with_master())
+        .and(warp::header::optional::<String>("content-type"))
+        .and(warp::body::content_length_limit(service::MAX_UI_BUNDLE))
+        .and(warp::body::bytes())
+        .and_then(
+            |name: String, content_type: Option<String>, body: Bytes| async move {
+                let is_json = content_type.map_or(false, |ct| ct.starts_with("application/json"));
+                let result = match is_json {
+                    true => match serde_json::from_slice::<IInstallUiDTO>(&body) {
+                        Ok(body) => service::install_url(&decode(&name), &body.url).await,
+                        Err(err) => Err(config::InvalidEdit(err.to_string()).into()),
+                    },
+                    false => service::install_zip(&decode(&name), body).await,
+                };
+                Ok::<_, Rejection>(empty_result(result))
+            },
+        )
+        .boxed()
+}
+
+/// DELETE /api/v1/ui/{name}
+pub fn delete_ui() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("ui" / String))
+        .and(warp::delete())
+        .and(with_master())
+        .map(|name: String| empty_result(service::remove_ui(&decode(&name))))
+        .boxed()
+}
+
+/// GET /api/v1/tokens
+pub fn get_tokens() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("tokens"))
+        .and(warp::get())
+        .and(with_master())
+        .map(|| {
+            let config = config::Sword::global().config.read();
+            let tokens = config
+                .web_tokens
+                .iter()
+                .flatten()
+                .map(|t| ITokenDTO {
+                    name: t.name.clone(),
+                    scopes: t.scopes.clone(),
+                    created_at: t.created_at,
+                    expires_at: t.expires_at,
+                    expired: t.is_expired(),
+                })
+                .collect::<Vec<_>>();
+            warp::reply::json(&tokens)
+        })
+        .boxed()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub(super) struct IMintTokenDTO {
+    pub name: String,
+    pub scopes: Vec<TokenScope>,
+    /// 有效期，秒
+    pub expires_in: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub(super) struct INewTokenDTO {
+    pub name: String,
+    pub token: String,
+}
+
+/// POST /api/v1/tokens
+pub fn post_token() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("tokens"))
+        .and(warp::post())
+        .and(with_master())
+        .and(warp::body::json())
+        .map(|body: IMintTokenDTO| {
+            let sword = config::Sword::global();
+            audit::track(
+                || match sword.mint_token(&body.name, body.scopes, body.expires_in) {
+                    Ok(token) => {
+                        let reply = warp::reply::json(&INewTokenDTO {
+                            name: body.name,
+                            token,
+                        });
+                        warp::reply::with_status(reply, StatusCode::CREATED).into_response()
+                    }
+                    Err(err) => error_response(err),
+                },
+            )
+        })
+        .boxed()
+}
+
+/// DELETE /api/v1/tokens/{name}
+pub fn delete_token() -> BoxedFilter<(impl warp::Reply,)> {
+    api()
+        .and(warp::path!("tokens" / String))
+        .and(warp::delete())
+        .and(with_master())
+        .map(|name: String| {
+            audit::track(|| empty_result(config::Sword::global().revoke_token(&decode(&name))))
+        })
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    #[test]
+    fn pointer_requires_a_path() {
+        let pointer = |path: &str| {
+            let filter = warp::path("sing_box").and(json_pointer());
+            block_on(warp::test::request().path(path).filter(&filter)).ok()
+        };
+        assert_eq!(pointer("/sing_box"), None);
+        assert_eq!(pointer("/sing_box/"), None);
+        assert_eq!(pointer("/sing_box/log/level"), Some("/log/level".into()));
+        assert_eq!(pointer("/sing_box/a~1b%20c"), Some("/a~1b c".into()));
+    }
+
+    #[test]
+    fn forwards_raw_query_without_token() {
+        assert_eq!(forward_query(""), "");
+        assert_eq!(forward_query("token=abc"), "");
+        assert_eq!(
+            forward_query("a=1&token=abc&a=2&b=x%20y&tokens=1"),
+            "a=1&a=2&b=x%20y&tokens=1"
+        );
+    }
+
+    #[test]
+    fn forwards_end_to_end_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", "application/json".parse().unwrap());
+        headers.insert("accept", "*/*".parse().unwrap());
+        headers.insert("if-none-match", "\"a\"".parse().unwrap());
+        headers.insert("authorization", "Bearer sword".parse().unwrap());
+        headers.insert("connection", "keep-alive, x-private".parse().unwrap());
+        headers.insert("x-private", "1".parse().unwrap());
+        headers.insert("transfer-encoding", "chunked".parse().unwrap());
+
+        let forwarded = end_to_end(&headers, &["authorization"]);
+        let mut names = forwarded.keys().map(|k| k.as_str()).collect::<Vec<_>>();
+        names.sort_unstable();
+        assert_eq!(names, ["accept", "content-type", "if-none-match"]);
+    }
+}
@@ -0,0 +1,233 @@
+use super::{
+    auth::{self, Forbidden, TooManyAttempts, Unauthorized},
+    limit::RateLimited,
+    listen::ClientAddr,
+};
+use crate::{
+    config::{self, Access, Sword},
+    utils::dirs,
+};
+use anyhow::Result;
+use chrono::Local;
+use json_patch::PatchOperation;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::{fs::OpenOptions, io::Write};
+use warp::{
+    filters::{path::FullPath, BoxedFilter},
+    hyper::{Method, StatusCode},
+    reply::Response,
+    Filter, Rejection, Reply,
+};
+
+/// 单条记录最多保留的修改项
+const MAX_CHANGES: usize = 50;
+
+/// 审计日志的一条记录，每行一个 json
+#[derive(Debug, Clone, Serialize)]
+pub struct IAudit {
+    pub time: String,
+    /// master、token:<name> 或 anonymous
+    pub who: String,
+    /// 来源 ip，unix socket 为空
+    pub remote: Option<String>,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    /// 配置的变化，只记录操作和路径，不记录值
+    pub changes: Vec<String>,
+}
+
+/// 只记录会修改状态的请求
+fn is_mutating(method: &Method) -> bool {
+    !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+/// sword 配置、sing-box 配置、配置片段和密文的快照
+fn snapshot() -> Value {
+    let sword = Sword::global();
+    let config = sword.config.read().clone();
+    let layers = config
+        .layers
+        .iter()
+        .flatten()
+        .map(|layer| {
+            let value = config::read_layer(&layer.name).unwrap_or_default();
+            (layer.name.clone(), value)
+        })
+        .collect::<Map<_, _>>();
+    json!({
+        "sword": serde_json::to_value(&config).unwrap_or_default(),
+        "sing_box": serde_json::to_value(&*sword.sing_box.read()).unwrap_or_default(),
+        "layers": layers,
+        "secrets": config::stored_secrets().unwrap_or_default(),
+    })
+}
+
+/// 处理函数记录在响应里的修改摘要
+#[derive(Debug, Clone)]
+struct Changes(Vec<String>);
+
+/// 执行一次修改，并把前后快照的差异记录在响应里
+///
+/// 执行期间持有写锁，同时发生的其他修改不会混进这次的差异。
+pub fn track<R: Reply>(write: impl FnOnce() -> R) -> Response {
+    let _writes = Sword::global().writes.lock();
+    let before = snapshot();
+    let mut res = write().into_response();
+    let changes = changes(&before, &snapshot());
+    res.extensions_mut().insert(Changes(changes));
+    res
+}
+
+/// 两个快照之间的修改摘要，例如 `replace /sword/web_port`
+fn changes(before: &Value, after: &Value) -> Vec<String> {
+    let patch = json_patch::diff(before, after);
+    let total = patch.0.len();
+    let mut changes = patch
+        .0
+        .into_iter()
+        .take(MAX_CHANGES)
+        .map(|op| match op {
+            PatchOperation::Add(op) => format!("add {}", op.path),
+            PatchOperation::Remove(op) => format!("remove {}", op.path),
+            PatchOperation::Replace(op) => format!("replace {}", op.path),
+            PatchOperation::Move(op) => format!("move {} {}", op.from, op.path),
+            PatchOperation::Copy(op) => format!("copy {} {}", op.from, op.path),
+            PatchOperation::Test(op) => format!("test {}", op.path),
+        })
+        .collect::<Vec<_>>();
+    if total > MAX_CHANGES {
+        changes.push(format!("... and {} more", total - MAX_CHANGES));
+    }
+    changes
+}
+
+/// 鉴权失败或被限流的请求对应的状态码
+fn rejected_status(err: &Rejection) -> Option<StatusCode> {
+    if err.find::<Unauthorized>().is_some() {
+        Some(StatusCode::UNAUTHORIZED)
+    } else if err.find::<Forbidden>().is_some() {
+        Some(StatusCode::FORBIDDEN)
+    } else if err.find::<TooManyAttempts>().is_some() || err.find::<RateLimited>().is_some() {
+        Some(StatusCode::TOO_MANY_REQUESTS)
+    } else {
+        None
+    }
+}
+
+/// 请求的发起者
+fn who(authorization: Option<&str>) -> String {
+    let token = auth::request_token(authorization, None);
+    match Sword::global().access(token) {
+        Some(Access::Master) => "master".into(),
+        Some(Access::Token(token)) => format!("token:{}", token.name),
+        None => "anonymous".into(),
+    }
+}
+
+/// 追加到审计日志
+fn append(audit: &IAudit) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dirs::audit_log_path())?;
+    writeln!(file, "{}", serde_json::to_string(audit)?)?;
+    Ok(())
+}
+
+/// 记录修改请求的发起者、接口和配置的变化
+///
+/// 被鉴权拒绝或限流的修改请求也会记录，拒绝原样交给后面的处理。
+/// 配置的变化由处理函数通过 [`track`] 记录在响应里。
+pub fn record(routes: BoxedFilter<(Response,)>) -> BoxedFilter<(Response,)> {
+    let routes = routes
+        .map(Ok::<Response, Rejection>)
+        .or_else(|err| async move { Ok::<_, Rejection>((Err(err),)) });
+
+    warp::method()
+        .and(warp::header::optional::<String>("authorization"))
+        .map(|method: Method, authorization: Option<String>| {
+            // 在处理之前确定发起者，修改 web_secret 的请求也能记录原来的身份
+            let who = is_mutating(&method).then(|| who(authorization.as_deref()));
+            (method, who)
+        })
+        .untuple_one()
+        .and(warp::path::full())
+        .and(warp::ext::optional::<ClientAddr>())
+        .and(routes)
+        .and_then(
+            |method: Method,
+             who: Option<String>,
+             path: FullPath,
+             remote: Option<ClientAddr>,
+             result: Result<Response, Rejection>| async move {
+                let who = match who {
+                    Some(who) => who,
+                    None => return result,
+                };
+                let (status, changes) = match &result {
+                    Ok(res) => {
+                        let changes = res.extensions().get::<Changes>().cloned();
+                        (res.status(), changes.map(|c| c.0).unwrap_or_default())
+                    }
+                    Err(err) => match rejected_status(err) {
+                        Some(status) => (status, vec![]),
+                        None => return result,
+                    },
+                };
+                let audit = IAudit {
+                    time: Local::now().to_rfc3339(),
+                    who,
+                    remote: remote.map(|ClientAddr(addr)| addr.ip().to_string()),
+                    method: method.to_string(),
+                    path: path.as_str().into(),
+                    status: status.as_u16(),
+                    changes,
+                };
+                crate::log_err!(append(&audit));
+                result
+            },
+        )
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarizes_changes_without_values() {
+        let before = json!({"sword": {"web_port": 1}, "secrets": {"a": "x"}});
+        let after = json!({"sword": {"web_port": 2}, "secrets": {"a": "y", "b": "z"}});
+        let mut changes = changes(&before, &after);
+        changes.sort();
+        assert_eq!(
+            changes,
+            vec![
+                "add /secrets/b",
+                "replace /secrets/a",
+                "replace /sword/web_port"
+            ]
+        );
+    }
+
+    #[test]
+    fn limits_the_number_of_changes() {
+        let after = (0..MAX_CHANGES + 3)
+            .map(|i| (format!("k{i}"), json!(i)))
+            .collect::<Map<_, _>>();
+        let changes = changes(&json!({}), &Value::Object(after));
+        assert_eq!(changes.len(), MAX_CHANGES + 1);
+        assert_eq!(changes[MAX_CHANGES], "... and 3 more");
+    }
+
+    #[test]
+    fn only_auth_rejections_are_recorded() {
+        let status = rejected_status(&warp::reject::custom(Unauthorized));
+        assert_eq!(status, Some(StatusCode::UNAUTHORIZED));
+        let status = rejected_status(&warp::reject::custom(RateLimited));
+        assert_eq!(status, Some(StatusCode::TOO_MANY_REQUESTS));
+        assert_eq!(rejected_status(&warp::reject::not_found()), None);
+    }
+}
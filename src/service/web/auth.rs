@@ -0,0 +1,145 @@
+use super::listen::ClientAddr;
+use crate::config::{Access, Sword, TokenScope};
+use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+use warp::{
+    reject::{self, Reject},
+    Filter, Rejection,
+};
+
+/// 时间窗口内允许的失败次数
+const MAX_FAILURES: u32 = 5;
+
+/// 失败次数的统计窗口，同时也是锁定的时长
+const LOCKOUT: Duration = Duration::from_secs(300);
+
+#[derive(Debug)]
+pub struct Unauthorized;
+
+#[derive(Debug)]
+pub struct Forbidden;
+
+#[derive(Debug)]
+pub struct TooManyAttempts;
+
+impl Reject for Unauthorized {}
+impl Reject for Forbidden {}
+impl Reject for TooManyAttempts {}
+
+struct Failure {
+    count: u32,
+    since: Instant,
+}
+
+fn failures() -> &'static Mutex<HashMap<IpAddr, Failure>> {
+    static FAILURES: OnceCell<Mutex<HashMap<IpAddr, Failure>>> = OnceCell::new();
+    FAILURES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn is_locked(ip: IpAddr) -> bool {
+    failures().lock().get(&ip).map_or(false, |f| {
+        f.count >= MAX_FAILURES && f.since.elapsed() < LOCKOUT
+    })
+}
+
+fn record_failure(ip: IpAddr) {
+    let mut failures = failures().lock();
+    failures.retain(|_, f| f.since.elapsed() < LOCKOUT);
+
+    let failure = failures.entry(ip).or_insert(Failure {
+        count: 0,
+        since: Instant::now(),
+    });
+    failure.count += 1;
+    if failure.count == MAX_FAILURES {
+        log::warn!(target: "app", "too many failed logins from {ip}, locked");
+    }
+}
+
+/// 解析 query 参数，没有参数时为空
+pub fn query_map() -> impl Filter<Extract = (HashMap<String, String>,), Error = Rejection> + Clone {
+    warp::query::<HashMap<String, String>>()
+        .or_else(|_| async { Ok::<_, Rejection>((HashMap::new(),)) })
+}
+
+/// 请求携带的 token，兼容直接把 token 放在请求头中
+///
+/// query 为 None 时不接受 token 参数，避免 token 出现在日志和历史记录中。
+pub fn request_token<'a>(
+    auth: Option<&'a str>,
+    query: Option<&'a HashMap<String, String>>,
+) -> Option<&'a str> {
+    auth.map(|auth| auth.strip_prefix("Bearer ").unwrap_or(auth))
+        .or_else(|| query?.get("token").map(String::as_str))
+}
+
+/// 校验 Authorization 头中的 token 并要求指定的权限
+pub fn with_access(
+    scope: TokenScope,
+) -> impl Filter<Extract = (Access,), Error = Rejection> + Clone {
+    check_access(scope, false)
+}
+
+/// 同 with_access，还接受 token 参数，只用于无法设置请求头的 websocket 和 sse
+pub fn with_stream_auth(scope: TokenScope) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    check_access(scope, true).map(|_| ()).untuple_one()
+}
+
+fn check_access(
+    scope: TokenScope,
+    allow_query: bool,
+) -> impl Filter<Extract = (Access,), Error = Rejection> + Clone {
+    warp::ext::optional::<ClientAddr>()
+        .and(warp::header::optional::<String>("authorization"))
+        .and(query_map())
+        .and_then(
+            move |remote: Option<ClientAddr>,
+                  auth: Option<String>,
+                  query: HashMap<String, String>| async move {
+                let ip = remote.map(|ClientAddr(addr)| addr.ip());
+                if ip.map_or(false, is_locked) {
+                    return Err(reject::custom(TooManyAttempts));
+                }
+
+                let query = allow_query.then(|| &query);
+                let token = request_token(auth.as_deref(), query);
+                match Sword::global().access(token) {
+                    Some(access) if access.allows(scope) => Ok(access),
+                    Some(_) => Err(reject::custom(Forbidden)),
+                    None => {
+                        if let (Some(ip), Some(_)) = (ip, token) {
+                            record_failure(ip);
+                        }
+                        Err(reject::custom(Unauthorized))
+                    }
+                }
+            },
+        )
+}
+
+/// 不要求鉴权的接口中读取调用方的身份，token 无效时为 None，也不计入失败次数
+pub fn optional_access() -> impl Filter<Extract = (Option<Access>,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .map(|auth: Option<String>| Sword::global().access(request_token(auth.as_deref(), None)))
+}
+
+pub fn with_auth(scope: TokenScope) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    with_access(scope).map(|_| ()).untuple_one()
+}
+
+/// 只允许使用 web_secret 的请求，用于管理 token
+pub fn with_master() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    with_access(TokenScope::Read)
+        .and_then(|access: Access| async move {
+            match access.is_master() {
+                true => Ok(()),
+                false => Err(reject::custom(Forbidden)),
+            }
+        })
+        .untuple_one()
+}
@@ -0,0 +1,222 @@
+use crate::config::{ArrayStrategy, ILayer, ISword};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// 当前的接口版本
+pub const API_VERSION: &str = "v1";
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ILayerDTO {
+    pub name: String,
+    #[serde(default)]
+    pub arrays: ArrayStrategy,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disabled: Option<bool>,
+}
+
+/// sword 的配置，与保存到磁盘的 ISword 分开，已发布的字段只能新增不能改变含义
+///
+/// api token 通过 /api/v1/tokens 管理，不在这里返回
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ISwordDTO {
+    pub web_port: u16,
+    pub web_allow_lan: bool,
+    pub web_listen: Option<Vec<String>>,
+    pub web_unix_socket: Option<String>,
+    pub web_unix_socket_mode: Option<String>,
+    pub web_secret: Option<String>,
+    pub web_ui: Option<String>,
+    pub web_tls_cert: Option<String>,
+    pub web_tls_key: Option<String>,
+    pub web_lan_insecure: Option<bool>,
+    pub web_cors_origins: Option<Vec<String>>,
+    pub web_allowed_hosts: Option<Vec<String>>,
+    pub web_rate_limit: Option<u32>,
+    pub clash_ui: Option<String>,
+    pub core_name: Option<String>,
+    pub layers: Option<Vec<ILayerDTO>>,
+    pub latency_test_url: Option<String>,
+    pub latency_timeout: Option<u64>,
+    pub latency_concurrency: Option<usize>,
+    pub usage_quota: Option<HashMap<String, u64>>,
+}
+
+impl From<&ILayer> for ILayerDTO {
+    fn from(layer: &ILayer) -> Self {
+        ILayerDTO {
+            name: layer.name.clone(),
+            arrays: layer.arrays,
+            disabled: layer.disabled,
+        }
+    }
+}
+
+impl From<ILayerDTO> for ILayer {
+    fn from(layer: ILayerDTO) -> Self {
+        ILayer {
+            name: layer.name,
+            arrays: layer.arrays,
+            disabled: layer.disabled,
+        }
+    }
+}
+
+impl From<&ISword> for ISwordDTO {
+    fn from(config: &ISword) -> Self {
+        ISwordDTO {
+            web_port: config.web_port,
+            web_allow_lan: config.web_allow_lan,
+            web_listen: config.web_listen.clone(),
+            web_unix_socket: config.web_unix_socket.clone(),
+            web_unix_socket_mode: config.web_unix_socket_mode.clone(),
+            web_secret: config.web_secret.clone(),
+            web_ui: config.web_ui.clone(),
+            web_tls_cert: config.web_tls_cert.clone(),
+            web_tls_key: config.web_tls_key.clone(),
+            web_lan_insecure: config.web_lan_insecure,
+            web_cors_origins: config.web_cors_origins.clone(),
+            web_allowed_hosts: config.web_allowed_hosts.clone(),
+            web_rate_limit: config.web_rate_limit,
+            clash_ui: config.clash_ui.clone(),
+            core_name: config.core_name.clone(),
+            layers: config
+                .layers
+                .as_ref()
+                .map(|list| list.iter().map(Into::into).collect()),
+            latency_test_url: config.latency_test_url.clone(),
+            latency_timeout: config.latency_timeout,
+            latency_concurrency: config.latency_concurrency,
+            usage_quota: config.usage_quota.clone(),
+        }
+    }
+}
+
+impl ISwordDTO {
+    /// 应用到当前的配置上，接口中没有的字段保持不变
+    pub fn apply(self, current: &ISword) -> ISword {
+        ISword {
+            web_port: self.web_port,
+            web_allow_lan: self.web_allow_lan,
+            web_listen: self.web_listen,
+            web_unix_socket: self.web_unix_socket,
+            web_unix_socket_mode: self.web_unix_socket_mode,
+            web_secret: self.web_secret,
+            web_ui: self.web_ui,
+            web_tls_cert: self.web_tls_cert,
+            web_tls_key: self.web_tls_key,
+            web_lan_insecure: self.web_lan_insecure,
+            web_cors_origins: self.web_cors_origins,
+            web_allowed_hosts: self.web_allowed_hosts,
+            web_rate_limit: self.web_rate_limit,
+            clash_ui: self.clash_ui,
+            core_name: self.core_name,
+            layers: self
+                .layers
+                .map(|list| list.into_iter().map(Into::into).collect()),
+            latency_test_url: self.latency_test_url,
+            latency_timeout: self.latency_timeout,
+            latency_concurrency: self.latency_concurrency,
+            usage_quota: self.usage_quota,
+            ..current.clone()
+        }
+    }
+
+    /// 鉴权、监听和 ui 地址只有 web_secret 可以修改，其他调用方提交的值被忽略
+    ///
+    /// 托盘打开 ui 时会带上 web_secret，允许 token 修改 ui 地址会泄露 web_secret。
+    pub fn keep_protected(&mut self, current: &ISword) {
+        self.web_port = current.web_port;
+        self.web_allow_lan = current.web_allow_lan;
+        self.web_listen = current.web_listen.clone();
+        self.web_unix_socket = current.web_unix_socket.clone();
+        self.web_unix_socket_mode = current.web_unix_socket_mode.clone();
+        self.web_secret = current.web_secret.clone();
+        self.web_ui = current.web_ui.clone();
+        self.web_tls_cert = current.web_tls_cert.clone();
+        self.web_tls_key = current.web_tls_key.clone();
+        self.web_lan_insecure = current.web_lan_insecure;
+        self.web_cors_origins = current.web_cors_origins.clone();
+        self.web_allowed_hosts = current.web_allowed_hosts.clone();
+        self.web_rate_limit = current.web_rate_limit;
+        self.clash_ui = current.clash_ui.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_protected_fields() {
+        let current = ISword {
+            web_secret: Some("secret".into()),
+            web_ui: Some("http://localhost:9090".into()),
+            ..ISword::default()
+        };
+
+        let mut value = ISwordDTO::from(&current);
+        value.web_port = 1;
+        value.web_allow_lan = !current.web_allow_lan;
+        value.web_listen = Some(vec!["0.0.0.0:1".into()]);
+        value.web_unix_socket = Some("/tmp/sword.sock".into());
+        value.web_unix_socket_mode = Some("777".into());
+        value.web_secret = None;
+        value.web_ui = Some("http://attacker.example".into());
+        value.web_tls_cert = Some("cert.pem".into());
+        value.web_tls_key = Some("key.pem".into());
+        value.web_lan_insecure = Some(true);
+        value.web_cors_origins = Some(vec!["*".into()]);
+        value.web_allowed_hosts = Some(vec!["attacker.example".into()]);
+        value.web_rate_limit = Some(0);
+        value.clash_ui = Some("http://attacker.example".into());
+        value.latency_timeout = Some(1000);
+
+        value.keep_protected(&current);
+        let config = value.apply(&current);
+        let mut expected = current.clone();
+        expected.latency_timeout = Some(1000);
+        assert_eq!(
+            serde_json::to_value(&config).unwrap(),
+            serde_json::to_value(&expected).unwrap()
+        );
+    }
+}
+
+/// 客户端据此判断服务端支持的功能
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ICapabilitiesDTO {
+    pub api_version: String,
+    /// 支持的全部接口版本
+    pub versions: Vec<String>,
+    pub app_version: String,
+    pub features: Vec<String>,
+    /// 当前请求拥有的权限，使用 web_secret 时还包含 master
+    pub scopes: Vec<String>,
+}
+
+/// 已实现的功能，新增功能时追加
+pub const FEATURES: &[&str] = &[
+    "config",
+    "sing_box",
+    "sing_box_patch",
+    "sing_box_pointer",
+    "render",
+    "effective",
+    "layers",
+    "secrets",
+    "proxies",
+    "mode",
+    "latency",
+    "traffic",
+    "events",
+    "usage",
+    "quota",
+    "connections",
+    "clash_proxy",
+    "tokens",
+    "health",
+    "openapi",
+    "ui_bundles",
+    "external_ui",
+];
@@ -0,0 +1,116 @@
+use super::{
+    auth::{Forbidden, TooManyAttempts, Unauthorized},
+    limit::RateLimited,
+};
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::Value;
+use std::convert::Infallible;
+use warp::{
+    filters::body::BodyDeserializeError,
+    hyper::StatusCode,
+    reject::{
+        InvalidHeader, InvalidQuery, LengthRequired, MethodNotAllowed, MissingHeader,
+        PayloadTooLarge, UnsupportedMediaType,
+    },
+    reply::Response,
+    Rejection, Reply,
+};
+
+/// 所有接口统一的错误格式
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct IApiError {
+    /// 便于程序判断的错误码，例如 unauthorized、invalid_edit
+    pub code: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+}
+
+pub fn error_reply(status: StatusCode, code: &str, message: impl Into<String>) -> Response {
+    error_details(status, code, message, None)
+}
+
+pub fn error_details(
+    status: StatusCode,
+    code: &str,
+    message: impl Into<String>,
+    details: Option<Value>,
+) -> Response {
+    let body = IApiError {
+        code: code.into(),
+        message: message.into(),
+        details,
+    };
+    warp::reply::with_status(warp::reply::json(&body), status).into_response()
+}
+
+/// 把鉴权失败和 warp 自带的拒绝转换为统一的错误格式
+pub async fn handle_rejection(err: Rejection) -> Result<Response, Infallible> {
+    if err.find::<Unauthorized>().is_some() {
+        let res = error_reply(
+            StatusCode::UNAUTHORIZED,
+            "unauthorized",
+            "missing or invalid token",
+        );
+        let res = warp::reply::with_header(res, "www-authenticate", "Bearer");
+        return Ok(res.into_response());
+    }
+
+    let (status, code, message) = if err.find::<Forbidden>().is_some() {
+        (
+            StatusCode::FORBIDDEN,
+            "forbidden",
+            "permission denied".into(),
+        )
+    } else if err.find::<TooManyAttempts>().is_some() {
+        let message = "too many failed attempts, try again later".into();
+        (StatusCode::TOO_MANY_REQUESTS, "too_many_attempts", message)
+    } else if err.find::<RateLimited>().is_some() {
+        let message = "too many requests, slow down".into();
+        (StatusCode::TOO_MANY_REQUESTS, "rate_limited", message)
+    } else if err.is_not_found() {
+        (StatusCode::NOT_FOUND, "not_found", "not found".into())
+    } else if let Some(e) = err.find::<BodyDeserializeError>() {
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "invalid_body",
+            e.to_string(),
+        )
+    } else if let Some(e) = err.find::<InvalidQuery>() {
+        (StatusCode::BAD_REQUEST, "invalid_query", e.to_string())
+    } else if let Some(e) = err.find::<MissingHeader>() {
+        (StatusCode::BAD_REQUEST, "invalid_header", e.to_string())
+    } else if let Some(e) = err.find::<InvalidHeader>() {
+        (StatusCode::BAD_REQUEST, "invalid_header", e.to_string())
+    } else if let Some(e) = err.find::<UnsupportedMediaType>() {
+        (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "unsupported_media_type",
+            e.to_string(),
+        )
+    } else if let Some(e) = err.find::<PayloadTooLarge>() {
+        (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "payload_too_large",
+            e.to_string(),
+        )
+    } else if let Some(e) = err.find::<LengthRequired>() {
+        (
+            StatusCode::LENGTH_REQUIRED,
+            "length_required",
+            e.to_string(),
+        )
+    } else if let Some(e) = err.find::<MethodNotAllowed>() {
+        (
+            StatusCode::METHOD_NOT_ALLOWED,
+            "method_not_allowed",
+            e.to_string(),
+        )
+    } else {
+        log::error!(target: "app", "unhandled rejection: {err:?}");
+        let message = "internal server error".into();
+        (StatusCode::INTERNAL_SERVER_ERROR, "internal", message)
+    };
+    Ok(error_reply(status, code, message))
+}
@@ -0,0 +1,79 @@
+use super::listen::ClientAddr;
+use crate::config::Sword;
+use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
+use std::{collections::HashMap, net::IpAddr, time::Instant};
+use warp::{
+    reject::{self, Reject},
+    Filter, Rejection,
+};
+
+/// 每个客户端每分钟默认允许的请求数
+const DEFAULT_RATE_LIMIT: u32 = 600;
+
+/// 超过这个数量时清理空闲的客户端
+const MAX_CLIENTS: usize = 256;
+
+#[derive(Debug)]
+pub struct RateLimited;
+
+impl Reject for RateLimited {}
+
+/// 令牌桶，按时间匀速补充，最多积累一分钟的量
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+fn buckets() -> &'static Mutex<HashMap<IpAddr, Bucket>> {
+    static BUCKETS: OnceCell<Mutex<HashMap<IpAddr, Bucket>>> = OnceCell::new();
+    BUCKETS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// 取出一个令牌，没有剩余时返回 false
+fn acquire(ip: IpAddr, limit: u32) -> bool {
+    let capacity = limit as f64;
+    let now = Instant::now();
+
+    let mut buckets = buckets().lock();
+    if buckets.len() > MAX_CLIENTS {
+        buckets.retain(|_, b| now.duration_since(b.updated).as_secs() < 60);
+    }
+
+    let bucket = buckets.entry(ip).or_insert(Bucket {
+        tokens: capacity,
+        updated: now,
+    });
+    let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+    bucket.tokens = (bucket.tokens + elapsed * capacity / 60.0).min(capacity);
+    bucket.updated = now;
+
+    match bucket.tokens >= 1.0 {
+        true => {
+            bucket.tokens -= 1.0;
+            true
+        }
+        false => false,
+    }
+}
+
+/// 按来源 ip 限制请求频率，unix socket 的请求不限制
+pub fn with_rate_limit() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::ext::optional::<ClientAddr>()
+        .and_then(|remote: Option<ClientAddr>| async move {
+            let limit = Sword::global()
+                .config
+                .read()
+                .web_rate_limit
+                .unwrap_or(DEFAULT_RATE_LIMIT);
+
+            match remote {
+                Some(ClientAddr(addr)) if limit > 0 && !acquire(addr.ip(), limit) => {
+                    log::warn!(target: "app", "rate limit exceeded by {}", addr.ip());
+                    Err(reject::custom(RateLimited))
+                }
+                _ => Ok(()),
+            }
+        })
+        .untuple_one()
+}
@@ -0,0 +1,216 @@
+use crate::config::Sword;
+use anyhow::{bail, Result};
+use std::{
+    io::ErrorKind,
+    net::{IpAddr, SocketAddr, TcpListener},
+    thread,
+    time::Duration,
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::TlsAcceptor;
+use warp::{
+    filters::BoxedFilter,
+    hyper::{server::conn::Http, service::service_fn, Body, Request},
+    reply::Response,
+};
+
+/// 默认的 unix socket 权限，只允许当前用户访问
+#[cfg(unix)]
+const DEFAULT_SOCKET_MODE: u32 = 0o600;
+
+/// 重启时旧的服务器可能还没有释放端口
+const BIND_RETRIES: u32 = 5;
+
+/// 请求的来源地址，unix socket 的请求没有
+#[derive(Debug, Clone, Copy)]
+pub struct ClientAddr(pub SocketAddr);
+
+/// 已经绑定的监听
+pub enum Listener {
+    Tcp(TcpListener, Option<TlsAcceptor>),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixListener),
+}
+
+/// 解析监听地址，未配置时按 web_allow_lan 选择
+///
+/// 每一项可以是 ip 或 ip:port，省略端口时使用 web_port，
+/// 在 linux 等系统上监听 `::` 同时接受 ipv4 和 ipv6。
+pub fn listen_addrs() -> Result<Vec<SocketAddr>> {
+    let config = Sword::global().config.read();
+    let port = config.web_port;
+    let allow_lan = config.web_allow_lan;
+
+    let list = match &config.web_listen {
+        Some(list) => list.clone(),
+        None => match allow_lan {
+            true => vec!["0.0.0.0".into()],
+            false => vec!["127.0.0.1".into()],
+        },
+    };
+    drop(config);
+
+    let mut addrs = vec![];
+    for item in list {
+        let addr = match item.parse::<SocketAddr>() {
+            Ok(addr) => addr,
+            Err(_) => match item.parse::<IpAddr>() {
+                Ok(ip) => SocketAddr::new(ip, port),
+                Err(_) => bail!("invalid web listen address `{item}`"),
+            },
+        };
+        if !allow_lan && !addr.ip().is_loopback() {
+            bail!("listening on {addr} requires web_allow_lan");
+        }
+        addrs.push(addr);
+    }
+    Ok(addrs)
+}
+
+/// 是否有非本机的监听地址
+pub fn is_lan(addrs: &[SocketAddr]) -> bool {
+    addrs.iter().any(|addr| !addr.ip().is_loopback())
+}
+
+/// 同步绑定 tcp 地址，端口被占用等错误直接返回
+pub fn bind_tcp(addr: SocketAddr, tls: Option<TlsAcceptor>) -> Result<Listener> {
+    let mut retries = 0;
+    let listener = loop {
+        match TcpListener::bind(addr) {
+            Ok(listener) => break listener,
+            Err(err) if err.kind() == ErrorKind::AddrInUse && retries < BIND_RETRIES => {
+                retries += 1;
+                thread::sleep(Duration::from_millis(100));
+            }
+            Err(err) => return Err(err.into()),
+        }
+    };
+    listener.set_nonblocking(true)?;
+    Ok(Listener::Tcp(listener, tls))
+}
+
+/// 同步绑定 unix socket，供本机的工具使用
+#[cfg(unix)]
+pub fn bind_unix(path: &str, mode: Option<&str>) -> Result<Listener> {
+    use std::{
+        fs,
+        os::unix::fs::{FileTypeExt, PermissionsExt},
+        os::unix::net::UnixListener,
+    };
+
+    let mode = match mode {
+        Some(mode) => u32::from_str_radix(mode.trim_start_matches("0o"), 8)?,
+        None => DEFAULT_SOCKET_MODE,
+    };
+
+    // 上次运行残留的 socket 文件会导致绑定失败，其他文件不能删除
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => fs::remove_file(path)?,
+        Ok(_) => bail!("web_unix_socket `{path}` exists and is not a socket"),
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    listener.set_nonblocking(true)?;
+    Ok(Listener::Unix(listener))
+}
+
+#[cfg(not(unix))]
+pub fn bind_unix(_path: &str, _mode: Option<&str>) -> Result<Listener> {
+    bail!("unix socket is not supported on this platform")
+}
+
+impl Listener {
+    /// 接受连接，直到所在的任务被终止
+    pub async fn serve(self, routes: BoxedFilter<(Response,)>) -> Result<()> {
+        match self {
+            Listener::Tcp(listener, tls) => {
+                let listener = tokio::net::TcpListener::from_std(listener)?;
+                loop {
+                    let (stream, addr) = match listener.accept().await {
+                        Ok(accepted) => accepted,
+                        Err(err) => {
+                            log::warn!(target: "app", "web server accept: {err}");
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                            continue;
+                        }
+                    };
+
+                    let routes = routes.clone();
+                    let tls = tls.clone();
+                    tauri::async_runtime::spawn(async move {
+                        match tls {
+                            Some(tls) => match tls.accept(stream).await {
+                                Ok(stream) => serve_connection(stream, Some(addr), routes).await,
+                                Err(err) => log::debug!(target: "app", "tls handshake: {err}"),
+                            },
+                            None => serve_connection(stream, Some(addr), routes).await,
+                        }
+                    });
+                }
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let listener = tokio::net::UnixListener::from_std(listener)?;
+                loop {
+                    let stream = match listener.accept().await {
+                        Ok((stream, _)) => stream,
+                        Err(err) => {
+                            log::warn!(target: "app", "web server accept: {err}");
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                            continue;
+                        }
+                    };
+
+                    let routes = routes.clone();
+                    tauri::async_runtime::spawn(serve_connection(stream, None, routes));
+                }
+            }
+        }
+    }
+}
+
+/// 处理一个连接，把来源地址放到请求的扩展中供鉴权使用
+async fn serve_connection<IO>(io: IO, addr: Option<SocketAddr>, routes: BoxedFilter<(Response,)>)
+where
+    IO: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let filter = warp::service(routes);
+    let service = service_fn(move |mut req: Request<Body>| {
+        if let Some(addr) = addr {
+            req.extensions_mut().insert(ClientAddr(addr));
+        }
+        let mut filter = filter.clone();
+        warp::hyper::service::Service::call(&mut filter, req)
+    });
+
+    let conn = Http::new().serve_connection(io, service).with_upgrades();
+    if let Err(err) = conn.await {
+        log::debug!(target: "app", "web connection: {err}");
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::utils::temp_dir;
+    use std::fs;
+
+    #[test]
+    fn replaces_only_stale_sockets() {
+        let dir = temp_dir("socket");
+
+        let path = dir.join("sword.sock");
+        let path = path.to_str().unwrap();
+        drop(bind_unix(path, None).unwrap());
+        // 残留的 socket 文件可以替换
+        drop(bind_unix(path, Some("660")).unwrap());
+
+        let file = dir.join("notes.txt");
+        fs::write(&file, "keep me").unwrap();
+        assert!(bind_unix(file.to_str().unwrap(), None).is_err());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "keep me");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,273 @@
+use crate::{config::Sword, service, utils::dirs};
+use anyhow::{anyhow, bail, Result};
+use futures::future;
+use listen::Listener;
+use once_cell::sync::OnceCell;
+use parking_lot::RwLock;
+use percent_encoding::percent_decode_str;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::sync::Arc;
+use tauri::{async_runtime::JoinHandle, AppHandle};
+use warp::{
+    filters::{
+        path::{FullPath, Peek},
+        BoxedFilter,
+    },
+    http::Uri,
+    reply::Response,
+    Filter, Reply,
+};
+
+mod api;
+mod audit;
+mod auth;
+mod dto;
+mod error;
+mod limit;
+mod listen;
+mod openapi;
+mod origin;
+mod tls;
+
+#[derive(Debug, Clone, Default, Serialize, JsonSchema)]
+pub struct IWebStatus {
+    /// 正在监听的地址
+    pub listening: Vec<String>,
+    /// 绑定失败等错误
+    pub errors: Vec<String>,
+    /// 使用 https 时证书的指纹
+    pub tls_fingerprint: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Web {
+    pub web_handler: Arc<RwLock<Option<JoinHandle<()>>>>,
+
+    pub status: Arc<RwLock<IWebStatus>>,
+}
+
+impl Web {
+    pub fn global() -> &'static Web {
+        static WEB: OnceCell<Web> = OnceCell::new();
+        WEB.get_or_init(|| Web {
+            web_handler: Arc::new(RwLock::new(None)),
+            status: Arc::new(RwLock::new(IWebStatus::default())),
+        })
+    }
+
+    /// 是否使用 https
+    pub fn is_tls(&self) -> bool {
+        self.status.read().tls_fingerprint.is_some()
+    }
+
+    /// 启动/重启服务器，有地址绑定失败时返回错误，其余地址照常启动
+    pub fn run_web(&self, app_handle: &AppHandle) -> Result<()> {
+        let mut server_handler = self.web_handler.write();
+        server_handler.take().map(|sh| sh.abort());
+
+        let mut status = IWebStatus::default();
+        let result = self.bind(&mut status);
+        if let Err(err) = &result {
+            status.errors.push(err.to_string());
+        }
+        let listeners = result.unwrap_or_default();
+        *self.status.write() = status.clone();
+
+        if listeners.is_empty() {
+            bail!("{}", status.errors.join("; "));
+        }
+
+        let routes = routes(app_handle);
+        *server_handler = Some(tauri::async_runtime::spawn(async move {
+            let servers = listeners
+                .into_iter()
+                .map(|listener| listener.serve(routes.clone()));
+            let results = future::join_all(servers).await;
+            let err = results.into_iter().find_map(|r| r.err());
+            let err = err.unwrap_or_else(|| anyhow!("web server stopped"));
+            log::error!(target: "app", "{err}");
+
+            let mut status = Web::global().status.write();
+            status.listening.clear();
+            status.errors.push(err.to_string());
+        }));
+
+        match status.errors.is_empty() {
+            true => Ok(()),
+            false => bail!("{}", status.errors.join("; ")),
+        }
+    }
+
+    /// 同步绑定全部地址
+    fn bind(&self, status: &mut IWebStatus) -> Result<Vec<Listener>> {
+        let addrs = listen::listen_addrs()?;
+        // 局域网中的其他设备也能访问，必须设置密钥
+        let (_, _, secret, _) = Sword::global().web_info();
+        if listen::is_lan(&addrs) && secret.map_or(true, |s| s.is_empty()) {
+            bail!("web_secret is required when web_allow_lan is enabled");
+        }
+        // 局域网访问时默认使用 https，证书不可用时不启动服务器
+        let tls = match listen::is_lan(&addrs) {
+            true => tls::lan_tls(&addrs)?,
+            false => None,
+        };
+        let acceptor = match &tls {
+            Some(tls) => Some(tls::acceptor(tls)?),
+            None => None,
+        };
+        status.tls_fingerprint = tls.map(|tls| tls.fingerprint);
+
+        let scheme = match acceptor.is_some() {
+            true => "https",
+            false => "http",
+        };
+        let mut listeners = vec![];
+        for addr in addrs {
+            match listen::bind_tcp(addr, acceptor.clone()) {
+                Ok(listener) => {
+                    log::info!(target: "app", "launch web server on {addr}");
+                    status.listening.push(format!("{scheme}://{addr}"));
+                    listeners.push(listener);
+                }
+                Err(err) => status
+                    .errors
+                    .push(format!("failed to listen on {addr}: {err}")),
+            }
+        }
+
+        let config = Sword::global().config.read();
+        let mode = config.web_unix_socket_mode.clone();
+        let unix_socket = config.web_unix_socket.clone();
+        drop(config);
+
+        if let Some(path) = unix_socket {
+            match listen::bind_unix(&path, mode.as_deref()) {
+                Ok(listener) => {
+                    log::info!(target: "app", "launch web server on {path}");
+                    status.listening.push(format!("unix:{path}"));
+                    listeners.push(listener);
+                }
+                Err(err) => status
+                    .errors
+                    .push(format!("failed to listen on {path}: {err}")),
+            }
+        }
+
+        Ok(listeners)
+    }
+}
+
+/// 已安装的 ui，位于 /ui/<name>/
+fn ui_routes() -> BoxedFilter<(Response,)> {
+    // 缺少结尾的 / 时相对路径的资源无法加载
+    let redirect = warp::path!("ui" / String)
+        .and(warp::get())
+        .and(warp::path::full())
+        .and_then(|name: String, full: FullPath| async move {
+            if full.as_str().ends_with('/') || !service::has_ui(&name) {
+                return Err(warp::reject::not_found());
+            }
+            let uri = format!("/ui/{name}/")
+                .parse::<Uri>()
+                .map_err(|_| warp::reject())?;
+            Ok(warp::redirect::temporary(uri).into_response())
+        });
+    // 隐藏安装时的临时目录等以 . 开头的文件
+    let files = warp::path("ui")
+        .and(warp::get())
+        .and(warp::path::peek())
+        .and_then(|peek: Peek| async move {
+            let hidden = peek.segments().any(|seg| {
+                percent_decode_str(seg)
+                    .decode_utf8()
+                    .map_or(true, |seg| seg.starts_with('.'))
+            });
+            match hidden {
+                true => Err(warp::reject::not_found()),
+                false => Ok(()),
+            }
+        })
+        .untuple_one()
+        .and(warp::fs::dir(dirs::ui_dir()))
+        .map(Reply::into_response);
+
+    redirect.or(files).unify().boxed()
+}
+
+/// 全部接口，与 openapi 文档中的接口一一对应
+fn api_routes() -> BoxedFilter<(Response,)> {
+    let api = limit::with_rate_limit().and(
+        api::get_version()
+            .or(api::get_config())
+            .or(api::get_sing_box())
+            .or(api::put_config())
+            .or(api::put_sing_box())
+            .or(api::patch_sing_box())
+            .or(api::get_sing_box_pointer())
+            .or(api::put_sing_box_pointer())
+            .or(api::get_render())
+            .or(api::get_effective())
+            .or(api::get_layer())
+            .or(api::put_layer())
+            .or(api::delete_layer())
+            .or(api::get_secrets())
+            .or(api::put_secret())
+            .or(api::delete_secret())
+            .or(api::get_proxies())
+            .or(api::put_proxy())
+            .or(api::put_mode())
+            .or(api::get_latency())
+            .or(api::test_latency())
+            .or(api::get_traffic())
+            .or(api::get_events())
+            .or(api::get_usage())
+            .or(api::get_quota())
+            .or(api::get_connections())
+            .or(api::delete_connections())
+            .or(api::delete_connection())
+            .or(api::clash_ws())
+            .or(api::clash_proxy())
+            .or(api::get_ui())
+            .or(api::put_ui())
+            .or(api::delete_ui())
+            .or(api::get_tokens())
+            .or(api::post_token())
+            .or(api::delete_token())
+            .or(api::get_health())
+            .or(api::get_openapi())
+            .or(api::get_capabilities()),
+    );
+
+    api.map(Reply::into_response).boxed()
+}
+
+fn routes(app_handle: &AppHandle) -> BoxedFilter<(Response,)> {
+    let mut routes = audit::record(api_routes()).or(ui_routes()).unify().boxed();
+
+    // 启动静态服务器
+    if let Ok(dist_dir) = dirs::resources_dir(app_handle) {
+        let dist_dir = dist_dir.join("dist");
+        if dist_dir.exists() {
+            let index_file = warp::get()
+                .and(warp::path::end())
+                .and(warp::fs::file(dist_dir.join("index.html")));
+            let any_file = warp::get().and(warp::fs::dir(dist_dir));
+
+            log::info!(target: "app", "launch web server with ui");
+            routes = index_file
+                .or(any_file)
+                .or(routes)
+                .map(Reply::into_response)
+                .boxed();
+        } else {
+            log::error!(target: "app", "web dist folder not exists");
+        }
+    }
+
+    // 来源检查在所有路由之前，被拒绝的请求统一返回对应的状态码
+    origin::guard(routes)
+        .recover(error::handle_rejection)
+        .map(Reply::into_response)
+        .boxed()
+}
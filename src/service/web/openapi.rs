@@ -0,0 +1,504 @@
+use super::{
+    api::*,
+    dto::{ICapabilitiesDTO, ISwordDTO},
+    error::IApiError,
+};
+use crate::{
+    config::{Composed, ISingBox},
+    service::{IConnections, ILatency, IProxy, IQuota, ITraffic, IUiBundle, IUsage},
+    utils::{events::SwordEvent, init},
+};
+use once_cell::sync::OnceCell;
+use schemars::{
+    gen::{SchemaGenerator, SchemaSettings},
+    JsonSchema,
+};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+
+/// 接口需要的权限，对应 token 的 scopes
+#[derive(Debug, Clone, Copy)]
+enum Auth {
+    None,
+    Read,
+    ConfigWrite,
+    CoreControl,
+    /// 只能使用 web_secret
+    Master,
+}
+
+impl Auth {
+    fn name(&self) -> Option<&'static str> {
+        match self {
+            Auth::None => None,
+            Auth::Read => Some("read"),
+            Auth::ConfigWrite => Some("config_write"),
+            Auth::CoreControl => Some("core_control"),
+            Auth::Master => Some("master"),
+        }
+    }
+}
+
+/// 匹配剩余全部路径的参数，值可以包含斜杠
+const MULTI_SEGMENT: [&str; 2] = ["pointer", "path"];
+
+/// 一个接口的描述
+struct Operation {
+    method: &'static str,
+    path: &'static str,
+    value: Map<String, Value>,
+    responses: Map<String, Value>,
+}
+
+impl Operation {
+    fn new(method: &'static str, path: &'static str, summary: &str, auth: Auth) -> Operation {
+        let mut value = Map::new();
+        value.insert("summary".into(), summary.into());
+
+        // 路径中的 {name} 都是字符串参数
+        let params = path
+            .split('/')
+            .filter_map(|seg| seg.strip_prefix('{')?.strip_suffix('}'))
+            .map(|name| {
+                let mut param = json!({
+                    "name": name,
+                    "in": "path",
+                    "required": true,
+                    "schema": { "type": "string" },
+                });
+                if MULTI_SEGMENT.contains(&name) {
+                    param["description"] = "The rest of the path, may contain slashes".into();
+                }
+                param
+            })
+            .collect::<Vec<_>>();
+        if !params.is_empty() {
+            value.insert("parameters".into(), params.into());
+        }
+
+        match auth.name() {
+            Some(scope) => {
+                value.insert("security".into(), json!([{ "bearer": [] }]));
+                value.insert("x-required-scope".into(), scope.into());
+            }
+            None => {
+                value.insert("security".into(), json!([]));
+            }
+        }
+
+        Operation {
+            method,
+            path,
+            value,
+            responses: Map::new(),
+        }
+    }
+
+    fn query(mut self, name: &str, description: &str) -> Operation {
+        let param = json!({
+            "name": name,
+            "in": "query",
+            "required": false,
+            "description": description,
+            "schema": { "type": "string" },
+        });
+        match self.value.get_mut("parameters") {
+            Some(Value::Array(params)) => params.push(param),
+            _ => {
+                self.value.insert("parameters".into(), json!([param]));
+            }
+        }
+        self
+    }
+
+    fn body(mut self, schema: Value) -> Operation {
+        let body = json!({
+            "required": true,
+            "content": { "application/json": { "schema": schema } },
+        });
+        self.value.insert("requestBody".into(), body);
+        self
+    }
+
+    fn reply(self, status: u16, schema: Value) -> Operation {
+        self.content(status, "application/json", schema)
+    }
+
+    fn content(mut self, status: u16, content_type: &str, schema: Value) -> Operation {
+        let res = json!({
+            "description": description(status),
+            "content": { content_type: { "schema": schema } },
+        });
+        self.responses.insert(status.to_string(), res);
+        self
+    }
+
+    fn empty(mut self, status: u16) -> Operation {
+        let res = json!({ "description": description(status) });
+        self.responses.insert(status.to_string(), res);
+        self
+    }
+}
+
+fn description(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        _ => "",
+    }
+}
+
+fn schema<T: JsonSchema>(gen: &mut SchemaGenerator) -> Value {
+    serde_json::to_value(gen.subschema_for::<T>()).unwrap_or_default()
+}
+
+/// 全部接口的 OpenAPI 3.0 文档，首次请求时生成
+pub fn document() -> &'static Value {
+    static DOCUMENT: OnceCell<Value> = OnceCell::new();
+    DOCUMENT.get_or_init(generate)
+}
+
+fn generate() -> Value {
+    let gen = &mut SchemaSettings::openapi3().into_generator();
+    let any = json!({});
+    let etag = "Returns the current etag; send it back in If-Match to avoid lost updates";
+
+    let operations = vec![
+        Operation::new("get", "/api/v1/version", "App version", Auth::Read)
+            .reply(200, schema::<IVersionDTO>(gen)),
+        Operation::new(
+            "get",
+            "/api/v1/health",
+            "Web server and core health, with details for authenticated callers",
+            Auth::None,
+        )
+        .reply(200, schema::<IHealthDTO>(gen)),
+        Operation::new(
+            "get",
+            "/api/v1/capabilities",
+            "Supported versions and features, and the scopes of the caller",
+            Auth::Read,
+        )
+        .reply(200, schema::<ICapabilitiesDTO>(gen)),
+        Operation::new("get", "/api/v1/openapi.json", "This document", Auth::None)
+            .reply(200, any.clone()),
+        Operation::new("get", "/api/v1/config", etag, Auth::Read)
+            .reply(200, schema::<ISwordDTO>(gen)),
+        Operation::new(
+            "put",
+            "/api/v1/config",
+            "Replace the sword config; web_* fields, web_ui and clash_ui only change with web_secret",
+            Auth::ConfigWrite,
+        )
+        .body(schema::<ISwordDTO>(gen))
+        .empty(204),
+        Operation::new("get", "/api/v1/sing_box", etag, Auth::Read)
+            .reply(200, schema::<ISingBox>(gen)),
+        Operation::new(
+            "put",
+            "/api/v1/sing_box",
+            "Replace the profile",
+            Auth::ConfigWrite,
+        )
+        .body(schema::<ISingBox>(gen))
+        .empty(204),
+        Operation::new(
+            "patch",
+            "/api/v1/sing_box",
+            "Merge patch, or JSON patch with application/json-patch+json",
+            Auth::ConfigWrite,
+        )
+        .body(any.clone())
+        .empty(204),
+        Operation::new(
+            "get",
+            "/api/v1/sing_box/{pointer}",
+            "Read the value at a JSON pointer, which may contain slashes",
+            Auth::Read,
+        )
+        .reply(200, any.clone()),
+        Operation::new(
+            "put",
+            "/api/v1/sing_box/{pointer}",
+            "Write the value at a JSON pointer, which may contain slashes",
+            Auth::ConfigWrite,
+        )
+        .body(any.clone())
+        .empty(204),
+        Operation::new(
+            "get",
+            "/api/v1/render",
+            "Preview the config passed to the core",
+            Auth::Read,
+        )
+        .reply(200, schema::<ISingBox>(gen)),
+        Operation::new(
+            "get",
+            "/api/v1/effective",
+            "Composed config and the layer of each field",
+            Auth::Read,
+        )
+        .reply(200, schema::<Composed>(gen)),
+        Operation::new("get", "/api/v1/layers/{name}", "Read a layer", Auth::Read)
+            .reply(200, any.clone()),
+        Operation::new(
+            "put",
+            "/api/v1/layers/{name}",
+            "Write a layer",
+            Auth::ConfigWrite,
+        )
+        .body(any.clone())
+        .empty(204),
+        Operation::new(
+            "delete",
+            "/api/v1/layers/{name}",
+            "Delete a layer",
+            Auth::ConfigWrite,
+        )
+        .empty(204),
+        Operation::new(
+            "get",
+            "/api/v1/secrets",
+            "Names of stored secrets",
+            Auth::Read,
+        )
+        .reply(200, schema::<Vec<String>>(gen)),
+        Operation::new(
+            "put",
+            "/api/v1/secrets/{name}",
+            "Store a secret",
+            Auth::ConfigWrite,
+        )
+        .body(schema::<ISecretDTO>(gen))
+        .empty(204),
+        Operation::new(
+            "delete",
+            "/api/v1/secrets/{name}",
+            "Delete a secret",
+            Auth::ConfigWrite,
+        )
+        .empty(204),
+        Operation::new(
+            "get",
+            "/api/v1/proxies",
+            "Clash proxies and groups",
+            Auth::Read,
+        )
+        .reply(200, schema::<HashMap<String, IProxy>>(gen)),
+        Operation::new(
+            "put",
+            "/api/v1/proxies/{group}",
+            "Select a proxy in a group",
+            Auth::CoreControl,
+        )
+        .body(schema::<ISelectDTO>(gen))
+        .empty(204),
+        Operation::new(
+            "put",
+            "/api/v1/mode",
+            "Switch the routing mode",
+            Auth::CoreControl,
+        )
+        .body(schema::<IModeDTO>(gen))
+        .empty(204),
+        Operation::new(
+            "get",
+            "/api/v1/outbounds/latency",
+            "Last latency results",
+            Auth::Read,
+        )
+        .reply(200, schema::<HashMap<String, ILatency>>(gen)),
+        Operation::new(
+            "post",
+            "/api/v1/outbounds/latency",
+            "Test all outbounds",
+            Auth::CoreControl,
+        )
+        .reply(200, schema::<HashMap<String, ILatency>>(gen)),
+        Operation::new(
+            "get",
+            "/api/v1/stats/traffic",
+            "Server-sent events with the rates per second",
+            Auth::Read,
+        )
+        .query("token", "API token, for clients that cannot set headers")
+        .content(200, "text/event-stream", schema::<ITraffic>(gen)),
+        Operation::new(
+            "get",
+            "/api/v1/events",
+            "Server-sent events when the config or the core changes",
+            Auth::Read,
+        )
+        .query("token", "API token, for clients that cannot set headers")
+        .content(200, "text/event-stream", schema::<SwordEvent>(gen)),
+        Operation::new(
+            "get",
+            "/api/v1/stats/usage/{period}",
+            "Usage per outbound, period is daily or monthly",
+            Auth::Read,
+        )
+        .query("from", "First date, inclusive")
+        .query("to", "Last date, inclusive")
+        .reply(
+            200,
+            schema::<BTreeMap<String, BTreeMap<String, IUsage>>>(gen),
+        ),
+        Operation::new(
+            "get",
+            "/api/v1/stats/quota",
+            "Monthly quota usage",
+            Auth::Read,
+        )
+        .reply(200, schema::<Vec<IQuota>>(gen)),
+        Operation::new(
+            "get",
+            "/api/v1/connections",
+            "Active connections",
+            Auth::Read,
+        )
+        .reply(200, schema::<IConnections>(gen)),
+        Operation::new(
+            "delete",
+            "/api/v1/connections",
+            "Close all connections",
+            Auth::CoreControl,
+        )
+        .empty(204),
+        Operation::new(
+            "delete",
+            "/api/v1/connections/{id}",
+            "Close a connection",
+            Auth::CoreControl,
+        )
+        .empty(204),
+        Operation::new(
+            "get",
+            "/clash/{path}",
+            "Forwarded to the clash api, path may contain slashes; other methods need core_control",
+            Auth::Read,
+        )
+        .reply(200, any),
+        Operation::new("get", "/api/v1/ui", "Installed UI bundles", Auth::Read)
+            .reply(200, schema::<Vec<IUiBundle>>(gen)),
+        Operation::new(
+            "put",
+            "/api/v1/ui/{name}",
+            "Install a UI bundle from an application/zip body or a JSON download url",
+            Auth::Master,
+        )
+        .body(schema::<IInstallUiDTO>(gen))
+        .empty(204),
+        Operation::new(
+            "delete",
+            "/api/v1/ui/{name}",
+            "Remove a UI bundle",
+            Auth::Master,
+        )
+        .empty(204),
+        Operation::new("get", "/api/v1/tokens", "List API tokens", Auth::Master)
+            .reply(200, schema::<Vec<ITokenDTO>>(gen)),
+        Operation::new(
+            "post",
+            "/api/v1/tokens",
+            "Create an API token",
+            Auth::Master,
+        )
+        .body(schema::<IMintTokenDTO>(gen))
+        .reply(201, schema::<INewTokenDTO>(gen)),
+        Operation::new(
+            "delete",
+            "/api/v1/tokens/{name}",
+            "Revoke an API token",
+            Auth::Master,
+        )
+        .empty(204),
+    ];
+
+    let error = json!({
+        "description": "Error",
+        "content": { "application/json": { "schema": schema::<IApiError>(gen) } },
+    });
+
+    let mut paths = Map::new();
+    for op in operations {
+        let Operation {
+            method,
+            path,
+            mut value,
+            mut responses,
+        } = op;
+        responses.insert("default".into(), error.clone());
+        value.insert("responses".into(), responses.into());
+
+        let item = paths.entry(path).or_insert_with(|| json!({}));
+        item[method] = value.into();
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "sing-sword",
+            "description": "Paths without a version, such as /api/config, are aliases of /api/v1",
+            "version": init::app_version(),
+        },
+        "paths": paths,
+        "components": {
+            "schemas": gen.take_definitions(),
+            "securitySchemes": {
+                "bearer": { "type": "http", "scheme": "bearer" },
+            },
+        },
+        "security": [{ "bearer": [] }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use warp::{http::HeaderValue, reject::InvalidHeader};
+
+    const METHODS: [&str; 5] = ["get", "put", "patch", "post", "delete"];
+
+    /// 把路径参数换成示例的值
+    fn example_path(path: &str) -> String {
+        path.split('/')
+            .map(|seg| match seg {
+                "{pointer}" => "outbounds/0",
+                "{path}" => "configs/rules",
+                seg if seg.starts_with('{') => "example",
+                seg => seg,
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    #[test]
+    fn documents_registered_routes() {
+        // 无法解析的 authorization 让匹配的接口在读取请求头时被拒绝，不会执行处理函数
+        let authorization = HeaderValue::from_bytes(&[0x80]).unwrap();
+        let routes = super::super::api_routes();
+        let paths = document()["paths"].as_object().unwrap();
+        for (path, item) in paths {
+            for method in METHODS {
+                let req = warp::test::request()
+                    .method(&method.to_ascii_uppercase())
+                    .path(&example_path(path))
+                    .header("authorization", authorization.clone())
+                    .filter(&routes);
+                let registered = match block_on(req) {
+                    Ok(_) => true,
+                    Err(err) => err.find::<InvalidHeader>().is_some(),
+                };
+
+                // clash api 的其他方法在说明里注明
+                let documented = item.get(method).is_some();
+                if !documented && path.starts_with("/clash/") {
+                    continue;
+                }
+                assert_eq!(registered, documented, "{method} {path}");
+            }
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use super::auth::Forbidden;
+use crate::{config::Sword, service};
+use std::net::IpAddr;
+use warp::{
+    filters::BoxedFilter,
+    http::{uri::Authority, HeaderValue, Uri},
+    hyper::StatusCode,
+    reject,
+    reply::Response,
+    Filter, Rejection, Reply,
+};
+
+const ALLOW_METHODS: &str = "GET, POST, PUT, PATCH, DELETE";
+const ALLOW_HEADERS: &str = "authorization, content-type, if-match";
+
+/// 主机名是否可信，域名需要在 web_allowed_hosts 中，防止 dns rebinding
+fn is_allowed_host(host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.eq_ignore_ascii_case("localhost") || host.parse::<IpAddr>().is_ok() {
+        return true;
+    }
+    let config = Sword::global().config.read();
+    config
+        .web_allowed_hosts
+        .iter()
+        .flatten()
+        .any(|h| h.eq_ignore_ascii_case(host))
+}
+
+/// 地址对应的 Origin，例如 https://yacd.haishan.me/ 为 https://yacd.haishan.me
+fn url_origin(url: &str) -> Option<String> {
+    let uri = url.parse::<Uri>().ok()?;
+    let scheme = uri.scheme_str().filter(|s| *s == "http" || *s == "https")?;
+    let authority = uri.authority()?;
+    Some(format!("{scheme}://{authority}").to_ascii_lowercase())
+}
+
+/// 默认允许的来源：配置为地址的 web_ui、clash_ui，以及核心提供的 external_ui
+fn ui_origins() -> Vec<String> {
+    let config = Sword::global().config.read();
+    let mut urls = [&config.web_ui, &config.clash_ui]
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect::<Vec<_>>();
+    drop(config);
+
+    let sing_box = Sword::global().sing_box.read();
+    let clash = sing_box
+        .experimental
+        .as_ref()
+        .and_then(|exp| exp.clash_api.as_ref())
+        .filter(|clash| clash.external_ui.is_some());
+    if let Some(clash) = clash {
+        if let Ok(controller) = service::controller_addr(&clash.external_controller) {
+            urls.push(format!("http://{controller}"));
+        }
+    }
+    urls.iter().filter_map(|url| url_origin(url)).collect()
+}
+
+/// 跨域的来源是否在 web_cors_origins 中，或是配置的 ui 的来源
+fn is_allowed_origin(origin: &str) -> bool {
+    let config = Sword::global().config.read();
+    let listed = config
+        .web_cors_origins
+        .iter()
+        .flatten()
+        .any(|o| o == "*" || o.trim_end_matches('/').eq_ignore_ascii_case(origin));
+    drop(config);
+
+    listed || ui_origins().iter().any(|o| o.eq_ignore_ascii_case(origin))
+}
+
+/// Origin 的 host:port 与请求的 Host 相同
+fn is_same_origin(origin: &str, host: &Authority) -> bool {
+    origin.split_once("://").map_or(false, |(_, authority)| {
+        authority.eq_ignore_ascii_case(host.as_str())
+    })
+}
+
+/// 检查 Host 和 Origin，拒绝来自其他网站的请求
+///
+/// 返回允许跨域访问的 Origin，同源或没有 Origin 时为空
+pub fn with_origin_check() -> impl Filter<Extract = (Option<String>,), Error = Rejection> + Clone {
+    warp::host::optional()
+        .and(warp::header::optional::<String>("origin"))
+        .and_then(
+            |host: Option<Authority>, origin: Option<String>| async move {
+                // 浏览器总会带上 Host，没有时是本机的工具通过 unix socket 访问
+                if let Some(host) = &host {
+                    if !is_allowed_host(host.host()) {
+                        log::warn!(target: "app", "reject request for host {host}");
+                        return Err(reject::custom(Forbidden));
+                    }
+                }
+                let same_origin =
+                    |origin: &str| host.as_ref().map_or(false, |h| is_same_origin(origin, h));
+                match origin {
+                    None => Ok(None),
+                    Some(origin) if same_origin(&origin) => Ok(None),
+                    Some(origin) if is_allowed_origin(&origin) => Ok(Some(origin)),
+                    Some(origin) => {
+                        log::warn!(target: "app", "reject request from origin {origin}");
+                        Err(reject::custom(Forbidden))
+                    }
+                }
+            },
+        )
+}
+
+/// 允许的跨域请求的预检
+fn preflight() -> BoxedFilter<(Response,)> {
+    warp::options()
+        .and(with_origin_check())
+        .map(|origin: Option<String>| {
+            let mut res = StatusCode::NO_CONTENT.into_response();
+            if let Some(origin) = origin {
+                let headers = res.headers_mut();
+                if let Ok(origin) = HeaderValue::from_str(&origin) {
+                    headers.insert("access-control-allow-origin", origin);
+                }
+                headers.insert(
+                    "access-control-allow-methods",
+                    HeaderValue::from_static(ALLOW_METHODS),
+                );
+                headers.insert(
+                    "access-control-allow-headers",
+                    HeaderValue::from_static(ALLOW_HEADERS),
+                );
+                headers.insert("access-control-max-age", HeaderValue::from_static("600"));
+            }
+            res
+        })
+        .boxed()
+}
+
+/// 为路由加上来源检查和跨域响应头
+pub fn guard(routes: BoxedFilter<(Response,)>) -> BoxedFilter<(Response,)> {
+    let routes =
+        with_origin_check()
+            .and(routes)
+            .map(|origin: Option<String>, mut res: Response| {
+                let headers = res.headers_mut();
+                headers.insert("vary", HeaderValue::from_static("origin"));
+                if let Some(origin) = origin.and_then(|o| HeaderValue::from_str(&o).ok()) {
+                    headers.insert("access-control-allow-origin", origin);
+                    headers.insert(
+                        "access-control-expose-headers",
+                        HeaderValue::from_static("etag"),
+                    );
+                }
+                res
+            });
+
+    preflight().or(routes).unify().boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_url_origins() {
+        let origin = |url: &str| url_origin(url);
+        assert_eq!(
+            origin("https://yacd.haishan.me/").as_deref(),
+            Some("https://yacd.haishan.me")
+        );
+        assert_eq!(
+            origin("http://127.0.0.1:9090/ui/?a=1").as_deref(),
+            Some("http://127.0.0.1:9090")
+        );
+        assert_eq!(
+            origin("HTTP://Example.COM:8080").as_deref(),
+            Some("http://example.com:8080")
+        );
+        // 已安装的 ui 的名字不是地址
+        assert_eq!(origin("yacd"), None);
+        assert_eq!(origin("file:///tmp/index.html"), None);
+    }
+}
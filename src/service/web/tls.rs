@@ -0,0 +1,177 @@
+use crate::{
+    config::{self, Sword},
+    utils::dirs,
+};
+use anyhow::{anyhow, bail, Result};
+use rustls_pemfile::Item;
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    sync::Arc,
+};
+use tokio_rustls::{
+    rustls::{Certificate, PrivateKey, ServerConfig},
+    TlsAcceptor,
+};
+
+/// web 服务器使用的证书
+#[derive(Debug, Clone)]
+pub struct ITls {
+    pub cert: Vec<u8>,
+    pub key: Vec<u8>,
+    /// 证书的 sha256 指纹，用于在客户端核对自签名证书
+    pub fingerprint: String,
+}
+
+/// 局域网访问时使用的证书，明确允许明文 http 时为空
+pub fn lan_tls(addrs: &[SocketAddr]) -> Result<Option<ITls>> {
+    let config = Sword::global().config.read();
+    let insecure = config.web_lan_insecure.unwrap_or(false);
+    let paths = (config.web_tls_cert.clone(), config.web_tls_key.clone());
+    drop(config);
+
+    if insecure {
+        log::warn!(target: "app", "web server allows plain http on lan");
+        return Ok(None);
+    }
+
+    let (cert, key) = match paths {
+        (Some(cert), Some(key)) => (fs::read(cert)?, fs::read(key)?),
+        (None, None) => self_signed(subject_names(addrs, &local_ips()))?,
+        _ => bail!("web_tls_cert and web_tls_key must be set together"),
+    };
+    let fingerprint = fingerprint(&cert)?;
+    Ok(Some(ITls {
+        cert,
+        key,
+        fingerprint,
+    }))
+}
+
+/// 读取或生成自签名证书，保存到配置目录，包含的名字变化时重新生成
+fn self_signed(names: Vec<String>) -> Result<(Vec<u8>, Vec<u8>)> {
+    let cert_path = dirs::web_cert_path();
+    let key_path = dirs::web_key_path();
+    let names_path = dirs::web_cert_names_path();
+    let saved = fs::read_to_string(&names_path).unwrap_or_default();
+    if cert_path.exists() && key_path.exists() && saved == names.join("\n") {
+        return Ok((fs::read(cert_path)?, fs::read(key_path)?));
+    }
+
+    let cert = rcgen::generate_simple_self_signed(names.clone())?;
+    let cert_pem = cert.serialize_pem()?;
+    let key_pem = cert.serialize_private_key_pem();
+
+    fs::write(&cert_path, cert_pem.as_bytes())?;
+    config::write_private(&key_path, key_pem.as_bytes())?;
+    fs::write(&names_path, names.join("\n").as_bytes())?;
+    log::info!(target: "app", "generated self-signed certificate for {}", names.join(", "));
+
+    Ok((cert_pem.into_bytes(), key_pem.into_bytes()))
+}
+
+/// 证书中的名字，包括本机回环和局域网客户端访问的地址
+///
+/// 监听所有地址时使用本机的局域网地址。
+fn subject_names(addrs: &[SocketAddr], local: &[IpAddr]) -> Vec<String> {
+    let mut ips: Vec<IpAddr> = vec![Ipv4Addr::LOCALHOST.into(), Ipv6Addr::LOCALHOST.into()];
+    for addr in addrs {
+        match addr.ip().is_unspecified() {
+            true => ips.extend_from_slice(local),
+            false => ips.push(addr.ip()),
+        }
+    }
+
+    let mut names = vec!["localhost".to_string()];
+    for ip in ips {
+        let ip = ip.to_string();
+        if !names.contains(&ip) {
+            names.push(ip);
+        }
+    }
+    names
+}
+
+/// 本机默认路由上的地址，udp 的 connect 只选择路由，不会发送数据
+fn local_ips() -> Vec<IpAddr> {
+    [
+        ("0.0.0.0:0", "192.0.2.1:80"),
+        ("[::]:0", "[2001:db8::1]:80"),
+    ]
+    .into_iter()
+    .filter_map(|(bind, target)| {
+        let socket = UdpSocket::bind(bind).ok()?;
+        socket.connect(target).ok()?;
+        Some(socket.local_addr().ok()?.ip())
+    })
+    .filter(|ip| !ip.is_loopback() && !ip.is_unspecified())
+    .collect()
+}
+
+/// 解析证书和私钥，证书无效时返回错误而不是在启动时 panic
+pub fn acceptor(tls: &ITls) -> Result<TlsAcceptor> {
+    let certs = rustls_pemfile::certs(&mut &tls.cert[..])?
+        .into_iter()
+        .map(Certificate)
+        .collect::<Vec<_>>();
+
+    let key = rustls_pemfile::read_all(&mut &tls.key[..])?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow!("no private key found in web_tls_key"))?;
+
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// 第一个证书的 sha256，格式为 AB:CD:...
+fn fingerprint(pem: &[u8]) -> Result<String> {
+    let der = rustls_pemfile::certs(&mut &pem[..])?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("no certificate found"))?;
+
+    let hex = Sha256::digest(&der)
+        .iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<Vec<_>>()
+        .join(":");
+    Ok(hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_include_listen_addresses() {
+        let addrs = [
+            "192.168.1.5:33211".parse().unwrap(),
+            "127.0.0.1:33211".parse().unwrap(),
+        ];
+        assert_eq!(
+            subject_names(&addrs, &[]),
+            ["localhost", "127.0.0.1", "::1", "192.168.1.5"]
+        );
+    }
+
+    #[test]
+    fn names_include_local_addresses_when_unspecified() {
+        let addrs = [
+            "0.0.0.0:33211".parse().unwrap(),
+            "[::]:33211".parse().unwrap(),
+        ];
+        let local = ["10.0.0.2".parse().unwrap(), "fd00::2".parse().unwrap()];
+        assert_eq!(
+            subject_names(&addrs, &local),
+            ["localhost", "127.0.0.1", "::1", "10.0.0.2", "fd00::2"]
+        );
+    }
+}
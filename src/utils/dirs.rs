@@ -35,11 +35,55 @@ pub fn sword_config_path() -> PathBuf {
     config_dir().join("sword.json")
 }
 
+/// 用户编辑的 sing-box 配置，可以包含模板变量
+pub fn profile_path() -> PathBuf {
+    config_dir().join("profile.json")
+}
+
+/// 本机的模板变量
+pub fn variables_path() -> PathBuf {
+    config_dir().join("variables.json")
+}
+
+/// 加密保存的密文
+pub fn secrets_path() -> PathBuf {
+    config_dir().join("secrets.json")
+}
+
+/// 未设置口令时加密密文使用的密钥
+pub fn secret_key_path() -> PathBuf {
+    config_dir().join("secret.key")
+}
+
+/// 口令派生密钥使用的盐
+pub fn secret_salt_path() -> PathBuf {
+    config_dir().join("secret.salt")
+}
+
+/// 自动生成的 web 服务器证书
+pub fn web_cert_path() -> PathBuf {
+    config_dir().join("web_cert.pem")
+}
+
+pub fn web_key_path() -> PathBuf {
+    config_dir().join("web_key.pem")
+}
+
+/// 自动生成的证书包含的名字，与监听地址不一致时重新生成
+pub fn web_cert_names_path() -> PathBuf {
+    config_dir().join("web_cert.names")
+}
+
+/// 叠加在 profile 之上的配置片段
+pub fn layers_dir() -> PathBuf {
+    config_dir().join("layers")
+}
+
 pub fn sing_box_dir() -> PathBuf {
     config_dir().join("sing")
 }
 
-/// sing-box配置路径
+/// 渲染后交给核心运行的 sing-box 配置路径
 pub fn sing_box_path() -> PathBuf {
     sing_box_dir().join("config.json")
 }
@@ -48,6 +92,21 @@ pub fn log_dir() -> PathBuf {
     app_dir().join("logs")
 }
 
+/// 管理接口的审计日志，只追加不轮转
+pub fn audit_log_path() -> PathBuf {
+    log_dir().join("audit.log")
+}
+
+/// 已安装的 web ui，每个子目录是一个 ui
+pub fn ui_dir() -> PathBuf {
+    app_dir().join("ui")
+}
+
+/// 按出站统计的流量记录
+pub fn usage_path() -> PathBuf {
+    app_dir().join("usage.json")
+}
+
 pub fn core_dir() -> Result<PathBuf> {
     Ok(tauri::utils::platform::current_exe()?
         .parent()
@@ -0,0 +1,69 @@
+use once_cell::sync::OnceCell;
+use schemars::JsonSchema;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+/// sword 的状态变化，通过 /api/events 推送给界面
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SwordEvent {
+    /// sword 配置已修改，etag 与 GET /api/v1/config 返回的一致
+    ConfigChanged {
+        etag: String,
+    },
+    /// sing-box 配置已修改
+    SingBoxChanged {
+        etag: String,
+    },
+    /// 配置片段已写入或删除
+    LayerChanged {
+        name: String,
+    },
+    CoreStarted {
+        core: String,
+    },
+    /// 重启或更换核心时停止了旧的进程
+    CoreStopped,
+    /// 核心意外退出
+    CoreCrashed {
+        code: Option<i32>,
+    },
+    CoreSwitched {
+        core: String,
+    },
+    /// 订阅者跟不上推送时丢失了事件，需要重新读取配置和状态
+    Resync {
+        missed: u64,
+    },
+}
+
+impl SwordEvent {
+    /// sse 的事件名
+    pub fn name(&self) -> &'static str {
+        match self {
+            SwordEvent::ConfigChanged { .. } => "config_changed",
+            SwordEvent::SingBoxChanged { .. } => "sing_box_changed",
+            SwordEvent::LayerChanged { .. } => "layer_changed",
+            SwordEvent::CoreStarted { .. } => "core_started",
+            SwordEvent::CoreStopped => "core_stopped",
+            SwordEvent::CoreCrashed { .. } => "core_crashed",
+            SwordEvent::CoreSwitched { .. } => "core_switched",
+            SwordEvent::Resync { .. } => "resync",
+        }
+    }
+}
+
+fn sender() -> &'static broadcast::Sender<SwordEvent> {
+    static SENDER: OnceCell<broadcast::Sender<SwordEvent>> = OnceCell::new();
+    SENDER.get_or_init(|| broadcast::channel(64).0)
+}
+
+/// 发布事件，没有订阅者时直接丢弃
+pub fn publish(event: SwordEvent) {
+    log::debug!(target: "app", "event {}", event.name());
+    let _ = sender().send(event);
+}
+
+pub fn subscribe() -> broadcast::Receiver<SwordEvent> {
+    sender().subscribe()
+}
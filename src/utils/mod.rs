@@ -1,6 +1,7 @@
 use std::path::PathBuf;
 
 pub mod dirs;
+pub mod events;
 pub mod init;
 
 pub const IDENTIFIER: &'static str = "sing-sword.com.github.zzzgydi";
@@ -43,6 +44,15 @@ macro_rules! notify_log_err {
     };
 }
 
+/// 测试使用的空临时目录，目录名带上进程 id，避免并行的测试互相影响
+#[cfg(test)]
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sing-sword-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
 pub fn open_by_code(path: &PathBuf) -> anyhow::Result<()> {
     #[cfg(target_os = "macos")]
     open::with(&path, "Visual Studio Code").or_else(|_| open::that(&path))?;